@@ -1,153 +1,402 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
-struct Node {
-    prev: u32,
-    next: u32,
+// A circle of marbles where every marble number is used once, so the number doubles as the index
+// of its links and the list is two plain vectors. Removed marbles keep their stale links.
+struct MarbleCircle {
+    start_value: u32,
+    len: usize,
+    prev: Vec<u32>,
+    next: Vec<u32>,
 }
 
-impl Node {
-    fn clone(&self) -> Node {
-        Node {
-            prev: self.prev,
-            next: self.next
-        }
+impl MarbleCircle {
+    fn new(start_value: u32) -> MarbleCircle {
+        MarbleCircle::with_capacity(start_value, start_value)
     }
-}
-
-struct HashList {
-    start_value: u32,
-    nodes: HashMap<u32, Node>
-}
 
-impl HashList {
-    fn new(start_value: u32) -> HashList {
-        let mut hash_list = HashList {
-            start_value: start_value,
-            nodes: HashMap::new()
+    // Room for marbles up to highest_value without growing
+    fn with_capacity(start_value: u32, highest_value: u32) -> MarbleCircle {
+        let size = highest_value.max(start_value) as usize + 1;
+        let mut circle = MarbleCircle {
+            start_value,
+            len: 1,
+            prev: vec![0; size],
+            next: vec![0; size],
         };
-        hash_list.nodes.insert(start_value, Node {prev: start_value, next: start_value});
-
-        hash_list
+        circle.prev[start_value as usize] = start_value;
+        circle.next[start_value as usize] = start_value;
+        circle
     }
 
     fn remove_value(&mut self, value_to_remove: u32) {
-        let middle = self.nodes.get(&value_to_remove).unwrap().clone();
-        let mut before = self.nodes.get(&middle.prev).unwrap().clone();
-        let mut after = self.nodes.get(&middle.next).unwrap().clone();
+        let prev = self.prev[value_to_remove as usize];
+        let next = self.next[value_to_remove as usize];
 
         if value_to_remove == self.start_value {
-            self.start_value = middle.next;
+            self.start_value = next;
         }
 
-        self.nodes.remove(&value_to_remove);
-
         // Fix up links
-        before.next = middle.next;
-        after.prev = middle.prev;
-
-        self.nodes.insert(middle.prev, before);
-        self.nodes.insert(middle.next, after);
+        self.next[prev as usize] = next;
+        self.prev[next as usize] = prev;
+        self.len -= 1;
     }
 
     fn insert_before(&mut self, value_to_insert: u32, before_this_value: u32) {
-        let mut after = self.nodes.get(&before_this_value).unwrap().clone();
-        let mut before = self.nodes.get(&after.prev).unwrap().clone();
-
-        // Insert new value
-        let middle = Node {
-            prev: after.prev,
-            next: before.next
-        };
-
-        // Fix up links
-        if self.nodes.len() == 1 {
-            self.nodes.insert(before_this_value, Node {
-                prev: value_to_insert,
-                next: value_to_insert
-            });
-        } else {
-            before.next = value_to_insert;
-            after.prev = value_to_insert;
-            self.nodes.insert(middle.prev, before);
-            self.nodes.insert(middle.next, after);
+        if value_to_insert as usize >= self.prev.len() {
+            self.prev.resize(value_to_insert as usize + 1, 0);
+            self.next.resize(value_to_insert as usize + 1, 0);
         }
-        self.nodes.insert(value_to_insert, middle);
+        let prev = self.prev[before_this_value as usize];
+
+        self.prev[value_to_insert as usize] = prev;
+        self.next[value_to_insert as usize] = before_this_value;
+        self.next[prev as usize] = value_to_insert;
+        self.prev[before_this_value as usize] = value_to_insert;
+        self.len += 1;
     }
 
     fn find(&self, starting_point: u32, num_steps: i32) -> u32 {
-        if num_steps == 0 {
-            return starting_point;
-        }
-
-        if num_steps < 0 {
-            return self.find(self.nodes.get(&starting_point).unwrap().prev, num_steps + 1);
-        } else {
-            return self.find(self.nodes.get(&starting_point).unwrap().next, num_steps - 1);
+        let mut current = starting_point;
+        for _ in 0..num_steps.abs() {
+            current = if num_steps < 0 { self.prev[current as usize] } else { self.next[current as usize] };
         }
+        current
     }
 
+    // Walks the whole circle once, every marble must be seen once and link back to the one before it
     fn validate(&self) {
-        let mut current_next = self.start_value;
-        let mut current_prev = self.start_value;
-        let mut next_hashes = HashSet::new();
-        let mut prev_hashes = HashSet::new();
-
-        for _ in 0..self.nodes.len() {
-            next_hashes.insert(current_next);
-            prev_hashes.insert(current_prev);
-            // println!("current_prev: {}, current_next {}", current_prev, current_next);
-            current_next = self.nodes.get(&current_next).expect("expecting next").next;
-            current_prev = self.nodes.get(&current_prev).expect("expecting prev").prev;
+        let mut seen = vec![false; self.next.len()];
+        let mut current = self.start_value;
+        for _ in 0..self.len {
+            if seen[current as usize] {
+                panic!("Not valid links anymore: {} is in the circle twice", current);
+            }
+            seen[current as usize] = true;
+            let next = self.next[current as usize];
+            if self.prev[next as usize] != current {
+                panic!("Not valid links anymore: {} -> {} but {} <- {}", current, next, self.prev[next as usize], next);
+            }
+            current = next;
         }
-
-        if current_prev != self.start_value ||
-            current_next != self.start_value ||
-            next_hashes.len() != self.nodes.len() || 
-            prev_hashes.len() != self.nodes.len() {
-            panic!("Not valid links anymore: nodes: {:?}\nnext_hashes {} -> {:?}\nprev_hashes {} {:?}",
-                self.nodes, 
-                next_hashes.len(), next_hashes, 
-                prev_hashes.len(), prev_hashes);
+        if current != self.start_value {
+            panic!("Not valid links anymore: {} marbles don't lead back to {}, ended at {}", self.len, self.start_value, current);
         }
     }
 }
 
-fn play_marbles(limit_players: usize, limit_marbles: u32) {
-    let mut player_score = Vec::new();
-    let mut placed_marbles = HashList::new(0u32);
-    for _ in 0..limit_players {
-        player_score.push(0u32);
+// What happens on every marble: a marble that is a multiple of special_multiple is kept and scores
+// together with the marble removal_offset steps from the current one, which is taken out, any other
+// marble goes in just before the one insertion_offset steps away and becomes the current one
+#[derive(Debug, Clone, Copy)]
+struct MarbleRules {
+    special_multiple: u32,
+    removal_offset: i32,
+    insertion_offset: i32,
+    // Keep every scoring marble in the result, off unless asked for with with_history
+    record_history: bool,
+}
+
+impl MarbleRules {
+    // A multiple of 1 would take out the last marble in the circle on the first turn
+    fn new(special_multiple: u32, removal_offset: i32, insertion_offset: i32) -> Result<MarbleRules, String> {
+        if special_multiple < 2 {
+            return Err(format!("special multiple {} should be at least 2", special_multiple));
+        }
+        Ok(MarbleRules { special_multiple, removal_offset, insertion_offset, record_history: false })
+    }
+
+    fn puzzle() -> MarbleRules {
+        MarbleRules::new(23, -7, 2).unwrap()
     }
 
+    fn with_history(self) -> MarbleRules {
+        MarbleRules { record_history: true, ..self }
+    }
+}
+
+#[derive(Debug)]
+struct GameResult {
+    // Final score of every player, player 1 first
+    scores: Vec<u64>,
+    // Index of the player with the highest score, the last one of them on a tie
+    winner: usize,
+    // The last marble the winner scored with, 0 if nobody scored
+    winning_marble: u32,
+    // Every scoring marble in order, only when the rules record the history
+    history: Vec<Scoring>,
+}
+
+// A player scoring with a marble and their score after it
+#[derive(Debug, Clone, PartialEq)]
+struct Scoring {
+    marble: u32,
+    player: usize,
+    score: u64,
+}
+
+// Someone went strictly ahead of everyone else, `from` is who had the lead before, if anyone did
+#[derive(Debug, PartialEq)]
+struct LeadChange {
+    marble: u32,
+    from: Option<usize>,
+    to: usize,
+}
+
+fn play_marbles(limit_players: usize, limit_marbles: u32, rules: &MarbleRules) -> GameResult {
+    let mut player_score = vec![0u64; limit_players];
+    let mut history = Vec::new();
+    let mut last_scored = vec![0u32; limit_players];
+    let mut placed_marbles = MarbleCircle::with_capacity(0u32, limit_marbles);
+
     let mut current_player = 0;
     let mut last_placed_marble = 0;
 
     for marble_to_place in 1..=limit_marbles {
-        if marble_to_place % 23 == 0 {
-            let remove_marble = placed_marbles.find(last_placed_marble, -7);
+        if marble_to_place % rules.special_multiple == 0 {
+            let remove_marble = placed_marbles.find(last_placed_marble, rules.removal_offset);
             last_placed_marble = placed_marbles.find(remove_marble, 1);
             placed_marbles.remove_value(remove_marble);
 
-            player_score[current_player] += remove_marble + marble_to_place;
+            player_score[current_player] += remove_marble as u64 + marble_to_place as u64;
+            last_scored[current_player] = marble_to_place;
+            if rules.record_history {
+                history.push(Scoring { marble: marble_to_place, player: current_player, score: player_score[current_player] });
+            }
         } else {
-            let insert_before_marble = placed_marbles.find(last_placed_marble, 2);
+            let insert_before_marble = placed_marbles.find(last_placed_marble, rules.insertion_offset);
             placed_marbles.insert_before(marble_to_place, insert_before_marble);
             last_placed_marble = marble_to_place;
         }
 
         current_player = (current_player + 1) % limit_players;
     }
+    if cfg!(debug_assertions) {
+        placed_marbles.validate();
+    }
+
+    let (winner, _) = player_score.iter().enumerate().max_by_key(|x| x.1).unwrap();
+    GameResult { winner, winning_marble: last_scored[winner], scores: player_score, history }
+}
 
-    let (winner, score) = player_score.iter().enumerate().max_by_key(|x| x.1).unwrap();
-    println!("Player {} has highest score of {}", winner + 1, score);
+fn print_winner(result: &GameResult) {
+    println!("Player {} has highest score of {}, last scoring with marble {}", result.winner + 1, result.scores[result.winner], result.winning_marble);
+}
+
+// Who is strictly ahead of everyone else after each scoring in a history, None while the top is tied
+fn leaders(history: &[Scoring]) -> Vec<Option<usize>> {
+    let mut top = 0;
+    let mut leader = None;
+    history.iter().map(|scoring| {
+        if scoring.score > top {
+            top = scoring.score;
+            leader = Some(scoring.player);
+        } else if scoring.score == top {
+            leader = None;
+        }
+        leader
+    }).collect()
+}
+
+// Every time someone other than the last leader went ahead, `from` is who had the lead last, if anyone did
+fn lead_changes(history: &[Scoring]) -> Vec<LeadChange> {
+    let mut last = None;
+    let mut changes = Vec::new();
+    for (scoring, leader) in history.iter().zip(leaders(history)) {
+        match leader {
+            Some(to) if leader != last => {
+                changes.push(LeadChange { marble: scoring.marble, from: last, to });
+                last = leader;
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+// The marble after which the winner stayed ahead of everyone until the end, None if the game ends in
+// a tie at the top
+fn lead_for_good(history: &[Scoring], winner: usize) -> Option<u32> {
+    let leaders = leaders(history);
+    if leaders.last() != Some(&Some(winner)) {
+        return None;
+    }
+    let since = leaders.iter().rposition(|leader| *leader != Some(winner)).map_or(0, |i| i + 1);
+    Some(history[since].marble)
+}
+
+// Players by score, best first, as (player index, score)
+fn standings(scores: &[u64]) -> Vec<(usize, u64)> {
+    let mut standings: Vec<(usize, u64)> = scores.iter().cloned().enumerate().collect();
+    standings.sort_by_key(|&(player, score)| (std::cmp::Reverse(score), player));
+    standings
+}
+
+// One line per scoring marble with the player's score after it and who leads, empty on a tie at the top
+fn history_csv(history: &[Scoring]) -> String {
+    let mut csv = String::from("marble,player,score,leader\n");
+    for (scoring, leader) in history.iter().zip(leaders(history)) {
+        csv += &format!("{},{},{},{}\n", scoring.marble, scoring.player + 1, scoring.score, leader.map(|p| (p + 1).to_string()).unwrap_or_default());
+    }
+    csv
+}
+
+// Times part 1 and part 2, which places 100 times as many marbles, a few runs each, and prints the
+// best time measured for each
+fn bench(runs: u32) {
+    for &(players, marbles) in &[(491, 71058), (491, 7105800)] {
+        let mut best = None;
+        for _ in 0..runs {
+            let start = Instant::now();
+            play_marbles(players, marbles, &MarbleRules::puzzle());
+            let elapsed = start.elapsed();
+            best = Some(best.map_or(elapsed, |best: Duration| best.min(elapsed)));
+        }
+        let best = best.unwrap();
+        println!("{} marbles: best of {} runs {:?}, {:.1} ns per marble", marbles, runs, best, best.as_nanos() as f64 / marbles as f64);
+    }
+}
+
+// Players, marbles and rules from `players marbles [multiple removal insertion]`, with the puzzle
+// rules unless all three are given
+fn parse_game(args: &[String]) -> Result<(usize, u32, MarbleRules), String> {
+    let count = |arg: &String, what: &str| u32::from_str(arg).map_err(|_| format!("{} should be a whole number, not '{}'", what, arg));
+    let players = count(&args[0], "players")?;
+    // Every marble goes to the next player round the table, so there has to be one
+    if players == 0 {
+        return Err("a game needs at least one player".to_string());
+    }
+    let marbles = count(&args[1], "marbles")?;
+    let rules = if args.len() == 5 {
+        let offset = |arg: &String, what: &str| i32::from_str(arg).map_err(|_| format!("{} should be a number, not '{}'", what, arg));
+        MarbleRules::new(count(&args[2], "multiple")?, offset(&args[3], "removal")?, offset(&args[4], "insertion")?)?
+    } else {
+        MarbleRules::puzzle()
+    };
+    Ok((players as usize, marbles, rules))
 }
 
 fn main() {
+    // `cargo run --release -- bench [runs]` times the puzzle games
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "bench" {
+        bench(args.get(2).map(|runs| runs.parse().expect("runs should be a number")).unwrap_or(5));
+        return;
+    }
+
+    // `cargo run -- play players marbles [multiple removal insertion]` plays a game, with the puzzle
+    // rules unless all three are given
+    if args.len() >= 2 && args[1] == "play" {
+        let game = if args.len() == 4 || args.len() == 7 { parse_game(&args[2..]) } else { Err("wrong number of arguments".to_string()) };
+        let (players, marbles, rules) = match game {
+            Ok(game) => game,
+            Err(e) => {
+                println!("{}\nusage: play players marbles [multiple removal insertion]", e);
+                return;
+            }
+        };
+        let result = play_marbles(players, marbles, &rules);
+        for (player, score) in result.scores.iter().enumerate() {
+            println!("Player {}: {}", player + 1, score);
+        }
+        print_winner(&result);
+        return;
+    }
+
+    // `cargo run -- history players marbles out.csv` follows the scores through a puzzle game and
+    // writes every scoring marble for plotting
+    if args.len() == 5 && args[1] == "history" {
+        let (players, marbles, rules) = match parse_game(&args[2..4]) {
+            Ok(game) => game,
+            Err(e) => {
+                println!("{}\nusage: history players marbles out.csv", e);
+                return;
+            }
+        };
+        let result = play_marbles(players, marbles, &rules.with_history());
+        let changes = lead_changes(&result.history);
+        println!("The lead changed hands {} times", changes.len());
+        for change in changes.iter().rev().take(10).rev() {
+            println!("  marble {}: player {} went ahead of {}", change.marble, change.to + 1, change.from.map(|p| format!("player {}", p + 1)).unwrap_or("nobody".to_string()));
+        }
+        println!("Standings:");
+        for (place, (player, score)) in standings(&result.scores).iter().take(10).enumerate() {
+            println!("  {}. player {} with {}", place + 1, player + 1, score);
+        }
+        match lead_for_good(&result.history, result.winner) {
+            Some(marble) => println!("Player {} led for good from marble {}", result.winner + 1, marble),
+            None => println!("The game ends tied at the top"),
+        }
+        fs::write(&args[4], history_csv(&result.history)).expect("could not write csv");
+        println!("Wrote {} scoring marbles to {}", result.history.len(), args[4]);
+        return;
+    }
+
+    // A small circle built by hand, walking both ways and taking out the start
+    let mut circle = MarbleCircle::new(0);
+    circle.insert_before(1, 0);
+    circle.insert_before(2, 0);
+    circle.insert_before(3, 1);
+    circle.validate();
+    assert!(circle.find(0, 1) == 3 && circle.find(0, 3) == 2 && circle.find(0, -1) == 2 && circle.find(3, -6) == 2);
+    circle.remove_value(0);
+    circle.validate();
+    assert!(circle.start_value == 3 && circle.find(2, 1) == 3);
+
+    let rules = MarbleRules::puzzle();
+    let test = play_marbles(9, 25, &rules);
+    assert!(test.scores == vec![0, 0, 0, 0, 32, 0, 0, 0, 0] && test.winner == 4 && test.winning_marble == 23);
+    assert!(play_marbles(10, 1618, &rules).scores.iter().max() == Some(&8317));
+    assert!(play_marbles(13, 7999, &rules).scores.iter().max() == Some(&146373));
+    assert!(play_marbles(17, 1104, &rules).scores.iter().max() == Some(&2764));
+    assert!(play_marbles(21, 6111, &rules).scores.iter().max() == Some(&54718));
+    assert!(play_marbles(30, 5807, &rules).scores.iter().max() == Some(&37305));
+    assert!(MarbleRules::new(1, -7, 2).is_err());
+    // Command line games, zero players or negative counts are an error instead of a panic or a
+    // huge number of marbles
+    let words = |line: &str| line.split_whitespace().map(|word| word.to_string()).collect::<Vec<String>>();
+    assert!(parse_game(&words("10 1618")).map(|(players, marbles, rules)| play_marbles(players, marbles, &rules).scores.iter().max() == Some(&8317)) == Ok(true));
+    assert!(parse_game(&words("0 10")).err() == Some("a game needs at least one player".to_string()));
+    assert!(parse_game(&words("-3 10")).err() == Some("players should be a whole number, not '-3'".to_string()));
+    assert!(parse_game(&words("3 -10")).is_err() && parse_game(&words("3 10 -23 -7 2")).is_err() && parse_game(&words("3 10 1 -7 2")).is_err());
+    assert!(parse_game(&words("3 10 2 -1 x")).err() == Some("insertion should be a number, not 'x'".to_string()));
+
+    // The history of the first sample has the one scoring marble
+    let with_history = rules.with_history();
+    let test = play_marbles(9, 25, &with_history);
+    assert!(test.history == vec![Scoring { marble: 23, player: 4, score: 32 }]);
+    assert!(lead_changes(&test.history) == vec![LeadChange { marble: 23, from: None, to: 4 }]);
+    assert!(lead_for_good(&test.history, test.winner) == Some(23));
+    // A tie at the top, player 2 catches up with player 1 and then passes
+    let tied = vec![Scoring { marble: 23, player: 0, score: 30 }, Scoring { marble: 46, player: 1, score: 30 }, Scoring { marble: 69, player: 1, score: 80 }];
+    assert!(leaders(&tied) == vec![Some(0), None, Some(1)]);
+    assert!(lead_changes(&tied) == vec![LeadChange { marble: 23, from: None, to: 0 }, LeadChange { marble: 69, from: Some(0), to: 1 }]);
+    assert!(lead_for_good(&tied[..2], 1).is_none() && lead_for_good(&tied, 1) == Some(69));
+
+    // The other samples, the last score of every player in the history is their final score and the
+    // last lead change goes to the winner, who led for good from then on
+    for &(players, marbles, high_score) in &[(10, 1618, 8317), (13, 7999, 146373), (17, 1104, 2764), (21, 6111, 54718), (30, 5807, 37305)] {
+        let sample = play_marbles(players, marbles, &with_history);
+        let mut replayed = vec![0u64; players];
+        sample.history.iter().for_each(|scoring| replayed[scoring.player] = scoring.score);
+        assert!(replayed == sample.scores && standings(&sample.scores)[0] == (sample.winner, high_score));
+        let changes = lead_changes(&sample.history);
+        assert!(changes.first().map(|change| change.from) == Some(None) && changes.last().map(|change| change.to) == Some(sample.winner));
+        assert!(lead_for_good(&sample.history, sample.winner) == Some(changes.last().unwrap().marble));
+    }
+    assert!(play_marbles(10, 1618, &rules).history.is_empty());
 
-    play_marbles(9, 25);     // test
-    play_marbles(13, 7999);     // test
-    play_marbles(491, 71058);     // Part1
-    play_marbles(491, 7105800);     // Part2
+    let part1 = play_marbles(491, 71058, &rules);
+    print_winner(&part1);
+    assert!(part1.scores[part1.winner] == 361466);
+    let part2 = play_marbles(491, 7105800, &rules);
+    print_winner(&part2);
+    assert!(part2.scores[part2.winner] == 2945918550);
+    // Scoring on every other marble, a single player passes what fits in 32 bits in a small game
+    let every_other = play_marbles(1, 100_000, &MarbleRules::new(2, -1, 1).unwrap());
+    print_winner(&every_other);
+    assert!(every_other.scores[0] == 4999950001 && every_other.scores[0] > u32::MAX as u64);
 }
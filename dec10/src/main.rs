@@ -2,6 +2,8 @@ extern crate regex;
 use regex::Regex;
 use std::collections::HashSet;
 
+mod ocr;
+
 #[derive(Debug)]
 struct Point {
     x: i32,
@@ -13,35 +15,70 @@ struct Point {
 impl Point {
     fn new(x: i32, y: i32, vel_x: i32, vel_y: i32) -> Point {
         Point {
-            x: x,
-            y: y,
-            vel_x: vel_x,
-            vel_y: vel_y
+            x,
+            y,
+            vel_x,
+            vel_y
         }
     }
 
-    fn update(&mut self) {
-        self.x += self.vel_x;
-        self.y += self.vel_y;
+    // Where the point is after the given number of seconds, negative goes back in time
+    fn at(&self, second: i32) -> (i32, i32) {
+        (self.x + self.vel_x * second, self.y + self.vel_y * second)
     }
 }
 
-fn is_cohesive(map: &HashSet<(i32, i32)>) -> bool {
-    // Message is cohesive is no single point is without connections
-    for loc in map {
-        if !map.contains(&(loc.0 - 1, loc.1)) &&
-           !map.contains(&(loc.0 + 1, loc.1)) &&
-           !map.contains(&(loc.0 - 1, loc.1 - 1)) &&
-           !map.contains(&(loc.0 + 1, loc.1 + 1)) &&
-           !map.contains(&(loc.0, loc.1 - 1)) &&
-           !map.contains(&(loc.0, loc.1 + 1)) &&
-           !map.contains(&(loc.0 - 1, loc.1 + 1)) &&
-           !map.contains(&(loc.0 + 1, loc.1 - 1)) {
-               return false;    // Not cohesive since this point is by itself
-           }
+// Area of the box around every point at a second
+fn bounding_area(points: &[Point], second: i32) -> i64 {
+    let positions: Vec<(i32, i32)> = points.iter().map(|p| p.at(second)).collect();
+    let width = positions.iter().map(|p| p.0).max().unwrap() - positions.iter().map(|p| p.0).min().unwrap();
+    let height = positions.iter().map(|p| p.1).max().unwrap() - positions.iter().map(|p| p.1).min().unwrap();
+    (width as i64 + 1) * (height as i64 + 1)
+}
+
+// The second where the points are closest together in the least squares sense. Every coordinate
+// moves linearly, so the spread around the mean is a parabola in time with its minimum at
+// -cov(position, velocity) / var(velocity).
+fn estimate_convergence(points: &[Point]) -> i32 {
+    let n = points.len() as f64;
+    let mean = |f: &dyn Fn(&Point) -> i32| points.iter().map(|p| f(p) as f64).sum::<f64>() / n;
+    let (x, y, vel_x, vel_y) = (mean(&|p| p.x), mean(&|p| p.y), mean(&|p| p.vel_x), mean(&|p| p.vel_y));
+    let mut cov = 0.0;
+    let mut var = 0.0;
+    for p in points {
+        cov += (p.x as f64 - x) * (p.vel_x as f64 - vel_x) + (p.y as f64 - y) * (p.vel_y as f64 - vel_y);
+        var += (p.vel_x as f64 - vel_x).powi(2) + (p.vel_y as f64 - vel_y).powi(2);
     }
+    if var == 0.0 { 0 } else { (-cov / var).round() as i32 }
+}
 
-    true
+// The second where the bounding box is smallest and the points then, starting from the estimate and
+// walking to the local minimum of the area in whichever direction it shrinks, which can be before 0
+fn converge(points: &[Point]) -> (i32, HashSet<(i32, i32)>) {
+    let mut second = estimate_convergence(points);
+    while bounding_area(points, second - 1) < bounding_area(points, second) {
+        second -= 1;
+    }
+    while bounding_area(points, second + 1) < bounding_area(points, second) {
+        second += 1;
+    }
+    (second, points.iter().map(|p| p.at(second)).collect())
+}
+
+fn parse(input: &str) -> Vec<Point> {
+    // position=< 9,  1> velocity=< 0,  2>
+    let reg = Regex::new(r"position=< *(-?\d+), *(-?\d+)> velocity=< *(-?\d+), *(-?\d+)>").unwrap();
+
+    let mut points = Vec::new();
+    for line in input.lines() {
+        let c = reg.captures(line).unwrap();
+        let x = c.get(1).unwrap().as_str().parse::<i32>().unwrap();
+        let y = c.get(2).unwrap().as_str().parse::<i32>().unwrap();
+        let vel_x = c.get(3).unwrap().as_str().parse::<i32>().unwrap();
+        let vel_y = c.get(4).unwrap().as_str().parse::<i32>().unwrap();
+        points.push(Point::new(x, y, vel_x, vel_y));
+    }
+    points
 }
 
 fn render(map: &HashSet<(i32, i32)>) {
@@ -63,32 +100,28 @@ fn render(map: &HashSet<(i32, i32)>) {
 }
 
 fn main() {
-    let input = include_str!("input");
-    // let input = include_str!("test");
-
-    // position=< 9,  1> velocity=< 0,  2>
-    let reg = Regex::new(r"position=< *(-?\d+), *(-?\d+)> velocity=< *(-?\d+), *(-?\d+)>").unwrap();
+    let test = parse(include_str!("test"));
+    let (second, map) = converge(&test);
+    println!("Test seconds: {}", second);
+    render(&map);
+    assert!(second == 3 && map.len() == test.len());
+    // The same sample moved on 50 seconds has to go back to find the message
+    let later: Vec<Point> = test.iter().map(|p| { let (x, y) = p.at(50); Point::new(x, y, p.vel_x, p.vel_y) }).collect();
+    assert!(converge(&later) == (-47, map.clone()));
+    // The sample is written in smaller letters that have to be added to the font first
+    let mut font = ocr::Font::block_letters();
+    let unknown = font.read(&map).unwrap_err();
+    assert!(unknown.starts_with("2 unknown glyphs"));
+    font.register('H', &["#...#", "#...#", "#...#", "#####", "#...#", "#...#", "#...#", "#...#"]);
+    font.register('I', &["###", ".#.", ".#.", ".#.", ".#.", ".#.", ".#.", "###"]);
+    assert!(font.read(&map) == Ok("HI".to_string()));
 
-    let mut points = Vec::new();
-    for line in input.lines() {
-        let c = reg.captures(line).unwrap();
-        let x = c.get(1).unwrap().as_str().parse::<i32>().unwrap();
-        let y = c.get(2).unwrap().as_str().parse::<i32>().unwrap();
-        let vel_x = c.get(3).unwrap().as_str().parse::<i32>().unwrap();
-        let vel_y = c.get(4).unwrap().as_str().parse::<i32>().unwrap();
-        points.push(Point::new(x, y, vel_x, vel_y));
-    }
-
-    for second in 1.. {
-        points.iter_mut().for_each(|p| p.update());
-        let map: HashSet<(i32, i32)> = points.iter()
-                    .map(|p| (p.x, p.y))
-                    .collect();
-        if is_cohesive(&map) {
-            let new_height = points.iter().max_by_key(|p| p.y).unwrap().y - points.iter().min_by_key(|p| p.y).unwrap().y;
-            println!("seconds: {}, message is {} high", second, new_height);
-            render(&map);
-            break;
-        }
-    }
+    let points = parse(include_str!("input"));
+    let (second, map) = converge(&points);
+    let height = map.iter().map(|p| p.1).max().unwrap() - map.iter().map(|p| p.1).min().unwrap();
+    println!("seconds: {}, message is {} high", second, height);
+    render(&map);
+    let message = ocr::Font::block_letters().read(&map).unwrap();
+    println!("The message reads {}", message);
+    assert!(second == 10867 && message == "FPZKLJZG");
 }
@@ -0,0 +1,77 @@
+// Reads the letters in a converged message.
+//
+// The message is cut into glyphs at the columns without any point, so letters have to be at least
+// one column apart. Each glyph is looked up by its rows of '#' and '.', as tall as the message.
+use std::collections::{HashMap, HashSet};
+
+// The 6 wide and 10 high block letters the star messages are written in
+const BLOCK_LETTERS: &[(char, [&str; 10])] = &[
+    ('A', ["..##..", ".#..#.", "#....#", "#....#", "#....#", "######", "#....#", "#....#", "#....#", "#....#"]),
+    ('B', ["#####.", "#....#", "#....#", "#....#", "#####.", "#....#", "#....#", "#....#", "#....#", "#####."]),
+    ('C', [".####.", "#....#", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#....#", ".####."]),
+    ('E', ["######", "#.....", "#.....", "#.....", "#####.", "#.....", "#.....", "#.....", "#.....", "######"]),
+    ('F', ["######", "#.....", "#.....", "#.....", "#####.", "#.....", "#.....", "#.....", "#.....", "#....."]),
+    ('G', [".####.", "#....#", "#.....", "#.....", "#.....", "#..###", "#....#", "#....#", "#...##", ".###.#"]),
+    ('H', ["#....#", "#....#", "#....#", "#....#", "######", "#....#", "#....#", "#....#", "#....#", "#....#"]),
+    ('J', ["...###", "....#.", "....#.", "....#.", "....#.", "....#.", "....#.", "#...#.", "#...#.", ".###.."]),
+    ('K', ["#....#", "#...#.", "#..#..", "#.#...", "##....", "##....", "#.#...", "#..#..", "#...#.", "#....#"]),
+    ('L', ["#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "######"]),
+    ('N', ["#....#", "##...#", "##...#", "#.#..#", "#.#..#", "#..#.#", "#..#.#", "#...##", "#...##", "#....#"]),
+    ('P', ["#####.", "#....#", "#....#", "#....#", "#####.", "#.....", "#.....", "#.....", "#.....", "#....."]),
+    ('R', ["#####.", "#....#", "#....#", "#....#", "#####.", "#..#..", "#...#.", "#...#.", "#....#", "#....#"]),
+    ('X', ["#....#", "#....#", ".#..#.", ".#..#.", "..##..", "..##..", ".#..#.", ".#..#.", "#....#", "#....#"]),
+    ('Z', ["######", ".....#", ".....#", "....#.", "...#..", "..#...", ".#....", "#.....", "#.....", "######"]),
+];
+
+pub struct Font {
+    glyphs: HashMap<Vec<String>, char>,
+}
+
+impl Font {
+    pub fn block_letters() -> Font {
+        let mut font = Font { glyphs: HashMap::new() };
+        for (letter, rows) in BLOCK_LETTERS {
+            font.register(*letter, rows);
+        }
+        font
+    }
+
+    // Adds a glyph or replaces the letter of one that is already known
+    pub fn register(&mut self, letter: char, rows: &[&str]) {
+        self.glyphs.insert(rows.iter().map(|row| row.to_string()).collect(), letter);
+    }
+
+    // The text in the points, or every glyph that isn't in the font drawn out
+    pub fn read(&self, map: &HashSet<(i32, i32)>) -> Result<String, String> {
+        if map.is_empty() {
+            return Ok(String::new());
+        }
+        let left_x = map.iter().map(|p| p.0).min().unwrap();
+        let right_x = map.iter().map(|p| p.0).max().unwrap();
+        let top_y = map.iter().map(|p| p.1).min().unwrap();
+        let bottom_y = map.iter().map(|p| p.1).max().unwrap();
+        let filled = |x: i32| (top_y..=bottom_y).any(|y| map.contains(&(x, y)));
+
+        let mut text = String::new();
+        let mut unknown = Vec::new();
+        let mut x = left_x;
+        while x <= right_x {
+            if !filled(x) {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x <= right_x && filled(x) {
+                x += 1;
+            }
+            let glyph: Vec<String> = (top_y..=bottom_y)
+                .map(|y| (start..x).map(|x| if map.contains(&(x, y)) { '#' } else { '.' }).collect())
+                .collect();
+            match self.glyphs.get(&glyph) {
+                Some(letter) => text.push(*letter),
+                None => unknown.push(format!("glyph {} at x {}:\n{}", text.len() + unknown.len() + 1, start, glyph.join("\n"))),
+            }
+        }
+        if unknown.is_empty() { Ok(text) } else { Err(format!("{} unknown glyphs\n{}", unknown.len(), unknown.join("\n"))) }
+    }
+}
@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+const GRID_SIZE: u32 = 300;
 
 fn power_level(x: u32, y: u32, grid_serial: u32) -> i32 {
     let rack_id = x + 10;
@@ -8,57 +11,130 @@ fn power_level(x: u32, y: u32, grid_serial: u32) -> i32 {
     ((power as i32 / 100) % 10) - 5
 }
 
-fn largest_area_sum(grid_serial: u32, size_range: std::ops::RangeInclusive<u32>) -> (u32, u32, u32, i32) {
-    let mut area_sum_cache: HashMap<(u32, u32), i32> = HashMap::new();
-    let mut largest_size = 1;
-    let mut largest_pos = (0, 0);
-    let mut largest_power = 0;
-    for current_size in size_range {
-        for y in 1..=300 - (current_size - 1) {
-            for x in 1..=300 - (current_size - 1) {
-                let mut power_sum = 0i32;
-                if let Some(cached_sum) = area_sum_cache.get(&(x, y)) {
-                    // Only need to run the right and bottom sides
-                    power_sum = *cached_sum;
-                    for sub_x in x..x+current_size - 1 {
-                        power_sum += power_level(sub_x, y+current_size-1, grid_serial);
-                    }
-                    for sub_y in y..y+current_size - 1 {
-                        power_sum += power_level(x+current_size-1, sub_y, grid_serial);
-                    }
-
-                    // Add corner
-                    power_sum += power_level(x+current_size-1, y+current_size-1, grid_serial);
-                } else {
-                    for sub_x in x..x+current_size {
-                        for sub_y in y..y+current_size {
-                            power_sum += power_level(sub_x, sub_y, grid_serial);
-                        }
-                    }
-                }
-                
-                area_sum_cache.insert((x, y), power_sum);
-
-                // Insert top left position with sum
-                if power_sum > largest_power {
-                    largest_power = power_sum;
-                    largest_size = current_size;
-                    largest_pos = (x, y)
+// A square as (x, y, size, power) with x and y its top left cell
+type Square = (u32, u32, u32, i32);
+
+// Summed-area table of a grid with cells from (1,1) to (width,height): sums holds the total of every
+// cell above and left of a position, with an extra row and column of zeroes so any square is
+// four lookups
+struct SummedArea {
+    width: u32,
+    height: u32,
+    sums: Vec<i32>,
+}
+
+impl SummedArea {
+    fn new<F: Fn(u32, u32) -> i32>(width: u32, height: u32, value: F) -> SummedArea {
+        let stride = width as usize + 1;
+        let mut sums = vec![0; stride * (height as usize + 1)];
+        for y in 1..=height as usize {
+            for x in 1..=width as usize {
+                sums[y * stride + x] = value(x as u32, y as u32) + sums[(y - 1) * stride + x] + sums[y * stride + x - 1]
+                    - sums[(y - 1) * stride + x - 1];
+            }
+        }
+        SummedArea { width, height, sums }
+    }
+
+    fn power_grid(width: u32, height: u32, grid_serial: u32) -> SummedArea {
+        SummedArea::new(width, height, |x, y| power_level(x, y, grid_serial))
+    }
+
+    // Sum of the size by size square with its top left cell at (x, y), which has to fit in the grid
+    fn square_sum(&self, x: u32, y: u32, size: u32) -> i32 {
+        let stride = self.width as usize + 1;
+        let (left, top) = (x as usize - 1, y as usize - 1);
+        let (right, bottom) = (left + size as usize, top + size as usize);
+        self.sums[bottom * stride + right] - self.sums[top * stride + right] - self.sums[bottom * stride + left] + self.sums[top * stride + left]
+    }
+
+    // Every square of a size that fits, in reading order of the top left cell
+    fn squares(&self, size: u32) -> impl Iterator<Item = Square> + '_ {
+        let fits_y = (self.height + 1).saturating_sub(size);
+        let fits_x = (self.width + 1).saturating_sub(size);
+        (1..=fits_y).flat_map(move |y| (1..=fits_x).map(move |x| (x, y, size, self.square_sum(x, y, size))))
+    }
+
+    // The square of a size with the most power, the first in reading order on a tie
+    fn best_square(&self, size: u32) -> Option<Square> {
+        self.squares(size).fold(None, |best: Option<Square>, square| match best {
+            Some(best) if best.3 >= square.3 => Some(best),
+            _ => Some(square),
+        })
+    }
+
+    // The k squares of any size with the most power, best first, smaller and earlier in reading
+    // order first on a tie
+    fn top_squares(&self, k: usize) -> Vec<Square> {
+        let rank = |&(x, y, size, power): &Square| (power, Reverse((size, y, x)));
+        // The worst of the best so far is on top so it is the one pushed out
+        let mut best = BinaryHeap::new();
+        for size in 1..=self.width.min(self.height) {
+            for square in self.squares(size) {
+                if best.len() < k {
+                    best.push(Reverse((rank(&square), square)));
+                } else if best.peek().is_some_and(|Reverse((worst, _))| rank(&square) > *worst) {
+                    best.pop();
+                    best.push(Reverse((rank(&square), square)));
                 }
             }
         }
+        let mut best: Vec<Square> = best.into_iter().map(|Reverse((_, square))| square).collect();
+        best.sort_by_key(|square| Reverse(rank(square)));
+        best
     }
-    (largest_pos.0, largest_pos.1, largest_size, largest_power)
+}
+
+// The square with the most power over every size in size_range that fits in the grid, the smallest
+// size on a tie. None when no size fits.
+fn largest_area_sum(grid: &SummedArea, size_range: std::ops::RangeInclusive<u32>) -> Option<Square> {
+    let fits = grid.width.min(grid.height);
+    size_range.take_while(|&size| size <= fits).filter_map(|size| grid.best_square(size)).fold(None, |best: Option<Square>, square| match best {
+        Some(best) if best.3 >= square.3 => Some(best),
+        _ => Some(square),
+    })
 }
 
 fn main() {
     let input = 8444;
-    // let input = 18; // Test
 
-    let part1 = largest_area_sum(input, 3..=3);
+    assert!(power_level(3, 5, 8) == 4 && power_level(122, 79, 57) == -5 && power_level(217, 196, 39) == 0 && power_level(101, 153, 71) == 4);
+    let grid_18 = SummedArea::power_grid(GRID_SIZE, GRID_SIZE, 18);
+    let grid_42 = SummedArea::power_grid(GRID_SIZE, GRID_SIZE, 42);
+    assert!(largest_area_sum(&grid_18, 3..=3) == Some((33, 45, 3, 29)));
+    assert!(largest_area_sum(&grid_42, 3..=3) == Some((21, 61, 3, 30)));
+    assert!(largest_area_sum(&grid_18, 1..=300) == Some((90, 269, 16, 113)));
+    assert!(largest_area_sum(&grid_42, 1..=300) == Some((232, 251, 12, 119)));
+
+    // Grids of other shapes, the sums match adding up the cells one by one
+    let grid = SummedArea::power_grid(40, 25, input);
+    assert!(grid.squares(20).count() == 21 * 6 && grid.squares(26).count() == 0 && grid.best_square(26).is_none());
+    for &(x, y, size) in &[(1, 1, 1), (3, 7, 5), (21, 6, 20), (1, 1, 25)] {
+        let direct: i32 = (x..x + size).flat_map(|x| (y..y + size).map(move |y| power_level(x, y, input))).sum();
+        assert!(grid.square_sum(x, y, size) == direct);
+    }
+    // Sizes past the smaller side of the grid are left out
+    assert!(largest_area_sum(&grid, 1..=300).is_some_and(|square| square.2 <= 25) && largest_area_sum(&grid, 26..=300).is_none());
+    assert!(largest_area_sum(&grid, 1..=25) == largest_area_sum(&grid, 1..=300));
+    // With every cell negative the best square is a single cell, the first one on a tie
+    let negative = SummedArea::new(4, 3, |x, y| if (x, y) == (3, 2) { -1 } else { -2 });
+    assert!(largest_area_sum(&negative, 1..=3) == Some((3, 2, 1, -1)));
+    assert!(largest_area_sum(&SummedArea::new(4, 3, |_, _| -3), 1..=3) == Some((1, 1, 1, -3)));
+    let top = grid_18.top_squares(3);
+    assert!(top[0] == (90, 269, 16, 113) && top.len() == 3 && top[1].3 <= 113 && top[2].3 <= top[1].3);
+
+    let grid = SummedArea::power_grid(GRID_SIZE, GRID_SIZE, input);
+    let part1 = largest_area_sum(&grid, 3..=3).unwrap();
     println!("Part1: area ({},{},{}) has largest power of {}", part1.0, part1.1, part1.2, part1.3);
+    assert!(part1 == (243, 68, 3, 28));
 
     // Part 2
-    let part2 = largest_area_sum(input, 1..=300);
+    let part2 = largest_area_sum(&grid, 1..=300).unwrap();
     println!("Part2: area ({},{},{}) has largest power of {}", part2.0, part2.1, part2.2, part2.3);
+    assert!(part2 == (236, 252, 12, 96));
+
+    println!("Top 5 squares of any size:");
+    for (x, y, size, power) in grid.top_squares(5) {
+        println!("  ({},{},{}) with power {}", x, y, size, power);
+    }
 }
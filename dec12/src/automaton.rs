@@ -0,0 +1,178 @@
+// A one dimensional cellular automaton on an endless row.
+//
+// Cells hold a state from 0 up to the number of symbols. The next state of a cell comes from the
+// cells within radius of it, read as a number in base states with the leftmost cell as the highest
+// digit. Past the stored cells the row is all one background state, which changes by the rule
+// for a neighbourhood of nothing but that state, so `..... => #` turns the whole endless row on.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+pub struct Automaton {
+    pub radius: usize,
+    pub symbols: Vec<char>,
+    // The next state for every neighbourhood
+    pub table: Vec<u8>,
+}
+
+impl Automaton {
+    fn empty(symbols: &[char], radius: usize) -> Result<Automaton, String> {
+        if symbols.len() < 2 || symbols.len() > u8::MAX as usize {
+            return Err(format!("{} states, there have to be between 2 and {}", symbols.len(), u8::MAX));
+        }
+        let size = (symbols.len() as u32).checked_pow(2 * radius as u32 + 1).filter(|size| *size <= 1 << 24)
+            .ok_or(format!("{} states with radius {} are too many neighbourhoods", symbols.len(), radius))?;
+        Ok(Automaton { radius, symbols: symbols.to_vec(), table: vec![0; size as usize] })
+    }
+
+    // Wolfram's numbering, digit i of number in base states is what grows from neighbourhood i, so
+    // rule 30 is wolfram(&['.', '#'], 1, 30)
+    pub fn wolfram(symbols: &[char], radius: usize, number: u128) -> Result<Automaton, String> {
+        let mut automaton = Automaton::empty(symbols, radius)?;
+        let states = symbols.len() as u128;
+        let mut rest = number;
+        for next in automaton.table.iter_mut() {
+            *next = (rest % states) as u8;
+            rest /= states;
+        }
+        if rest != 0 {
+            return Err(format!("rule {} is too big for {} states with radius {}", number, symbols.len(), radius));
+        }
+        Ok(automaton)
+    }
+
+    // Rules like `..#.. => #` as pairs of pattern and result, all patterns 2 * radius + 1 long.
+    // Neighbourhoods without a rule become default.
+    pub fn from_patterns(symbols: &[char], rules: &[(&str, char)], default: char) -> Result<Automaton, String> {
+        let width = rules.first().map_or(1, |(pattern, _)| pattern.chars().count());
+        if width.is_multiple_of(2) {
+            return Err(format!("patterns are {} long, they need a middle", width));
+        }
+        let mut automaton = Automaton::empty(symbols, width / 2)?;
+        let default = automaton.state(default)?;
+        automaton.table.iter_mut().for_each(|next| *next = default);
+        for (pattern, result) in rules {
+            if pattern.chars().count() != width {
+                return Err(format!("{} isn't {} long like the other patterns", pattern, width));
+            }
+            let mut index = 0;
+            for symbol in pattern.chars() {
+                index = index * symbols.len() + automaton.state(symbol)? as usize;
+            }
+            automaton.table[index] = automaton.state(*result)?;
+        }
+        Ok(automaton)
+    }
+
+    pub fn state(&self, symbol: char) -> Result<u8, String> {
+        self.symbols.iter().position(|s| *s == symbol).map(|state| state as u8).ok_or(format!("unknown state '{}'", symbol))
+    }
+}
+
+// A row of cells with cells[i] at position first + i, trimmed so the cells at both ends differ
+// from the background
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub first: i64,
+    pub cells: Vec<u8>,
+    pub background: u8,
+}
+
+impl Row {
+    pub fn new(first: i64, mut cells: Vec<u8>, background: u8) -> Row {
+        while cells.last() == Some(&background) {
+            cells.pop();
+        }
+        let lead = cells.iter().take_while(|cell| **cell == background).count();
+        Row { first: first + lead as i64, cells: cells.split_off(lead), background }
+    }
+
+    pub fn parse(automaton: &Automaton, text: &str, first: i64, background: char) -> Result<Row, String> {
+        let cells = text.chars().map(|symbol| automaton.state(symbol)).collect::<Result<Vec<u8>, String>>()?;
+        Ok(Row::new(first, cells, automaton.state(background)?))
+    }
+
+    pub fn get(&self, pos: i64) -> u8 {
+        if pos < self.first || pos >= self.first + self.cells.len() as i64 { self.background } else { self.cells[(pos - self.first) as usize] }
+    }
+
+    // Cells within radius of the stored ones can change, and the window slides one cell at a time
+    // dropping its highest digit
+    pub fn step(&self, automaton: &Automaton) -> Row {
+        let states = automaton.symbols.len();
+        let radius = automaton.radius as i64;
+        let highest = automaton.table.len() / states;
+        let background = automaton.table[(0..=2 * radius).fold(0, |index, _| index * states + self.background as usize)];
+
+        let start = self.first - radius;
+        let mut index = (start - radius..start + radius).fold(0, |index, pos| index * states + self.get(pos) as usize);
+        let mut cells = Vec::with_capacity(self.cells.len() + 2 * radius as usize);
+        for pos in start..self.first + self.cells.len() as i64 + radius {
+            index = index % highest * states + self.get(pos + radius) as usize;
+            cells.push(automaton.table[index]);
+        }
+        Row::new(start, cells, background)
+    }
+
+    pub fn render(&self, automaton: &Automaton) -> String {
+        self.cells.iter().map(|cell| automaton.symbols[*cell as usize]).collect()
+    }
+}
+
+// Anything that can be run looking for cycles: a row at a position with a pattern that doesn't
+// depend on where it is
+pub trait Generation: Clone {
+    type Pattern: Eq + Hash;
+    fn pattern(&self) -> Self::Pattern;
+    fn first(&self) -> i64;
+    fn moved(self, by: i64) -> Self;
+}
+
+impl Generation for Row {
+    type Pattern = (Vec<u8>, u8);
+
+    fn pattern(&self) -> (Vec<u8>, u8) {
+        (self.cells.clone(), self.background)
+    }
+
+    fn first(&self) -> i64 {
+        self.first
+    }
+
+    fn moved(mut self, by: i64) -> Row {
+        self.first += by;
+        self
+    }
+}
+
+// The row comes back every period generations from start on, moved shift cells to the right
+#[derive(Debug, PartialEq)]
+pub struct Cycle {
+    pub start: u64,
+    pub period: u64,
+    pub shift: i64,
+}
+
+// The row after the given number of generations. Runs until then or until a pattern shows up
+// again, which has to repeat from there on, and works out the rest from the cycle. Every row on
+// the way is kept to look for the cycle, so a run that needs more than max_states of them stops
+// with an error instead of growing without end.
+pub fn generation<T: Generation, F: Fn(&T) -> T>(initial: &T, generations: u64, max_states: usize, step: F) -> Result<(T, Option<Cycle>), String> {
+    let mut history = vec![initial.clone()];
+    let mut seen = HashMap::new();
+    seen.insert(initial.pattern(), 0);
+    for gen_id in 1..=generations {
+        let next = step(history.last().unwrap());
+        if let Some(&start) = seen.get(&next.pattern()) {
+            let cycle = Cycle { start, period: gen_id - start, shift: next.first() - history[start as usize].first() };
+            let (cycles, rest) = ((generations - start) / cycle.period, (generations - start) % cycle.period);
+            let row = history.swap_remove((start + rest) as usize).moved(cycles as i64 * cycle.shift);
+            return Ok((row, Some(cycle)));
+        }
+        if history.len() >= max_states {
+            return Err(format!("no cycle in the first {} generations, stopped at the limit of {} stored states", gen_id - 1, max_states));
+        }
+        seen.insert(next.pattern(), gen_id);
+        history.push(next);
+    }
+    Ok((history.pop().unwrap(), None))
+}
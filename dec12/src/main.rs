@@ -1,95 +1,264 @@
 extern crate regex;
 use regex::Regex;
-use std::collections::HashSet;
-
-fn print_state(state: &HashSet<i32>) -> String {
-    let min = *state.iter().min().unwrap();
-    let max = *state.iter().max().unwrap();
-    let mut res = String::new();
-    for i in min..=max {
-        res.push(if state.contains(&i) { '#' } else { '.' });
-    }
-    res
+use std::env;
+use std::fs;
+
+mod automaton;
+
+use automaton::{Automaton, Cycle, Generation, Row};
+
+// What grows from each neighbourhood of 5 pots, indexed by the neighbourhood read as bits with the
+// leftmost pot as the highest bit, so "#...#" is 0b10001
+type Rules = [bool; 32];
+
+// A row of pots with plants as bits, pot `first + i` is bit i % 64 of words[i / 64]. Kept trimmed so
+// bit 0 and bit len - 1 are plants and no word is empty past them, so the same pattern has the
+// same words wherever it is.
+#[derive(Clone, Debug, PartialEq)]
+struct Pots {
+    first: i64,
+    len: usize,
+    words: Vec<u64>,
 }
 
-fn populate_next(next_gen: &mut HashSet<i32>, current_gen: &HashSet<i32>, rules: &Vec<(&str, bool)>) {
-    let min = *current_gen.iter().min().unwrap();
-    let max = *current_gen.iter().max().unwrap();
-    for pot in min-5..=max+5 {
-        for (rule, does_create_new) in rules {
-            let mut rule_match = true;
-            for (offset, pot_info) in rule.chars().enumerate() {
-                match pot_info {
-                    '#' => if !current_gen.contains(&(pot + offset as i32 - 2)) { rule_match = false; },
-                    '.' => if current_gen.contains(&(pot + offset as i32 - 2)) { rule_match = false; },
-                    _ => panic!("bad rule")
-                }
-            }
+impl Pots {
+    fn from_plants(first: i64, plants: &[bool]) -> Pots {
+        let mut words = vec![0u64; plants.len().div_ceil(64)];
+        for (i, _) in plants.iter().enumerate().filter(|(_, plant)| **plant) {
+            words[i / 64] |= 1 << (i % 64);
+        }
+        Pots::trimmed(first, words)
+    }
+
+    // Moves the bits down so the first plant is bit 0 and drops the empty words after the last
+    fn trimmed(first: i64, mut words: Vec<u64>) -> Pots {
+        while words.last() == Some(&0) {
+            words.pop();
+        }
+        let lead = match words.iter().position(|w| *w != 0) {
+            Some(word) => word * 64 + words[word].trailing_zeros() as usize,
+            None => return Pots { first: 0, len: 0, words },
+        };
+        let (skip, shift) = (lead / 64, lead % 64);
+        let mut moved: Vec<u64> = (skip..words.len())
+            .map(|i| if shift == 0 { words[i] } else { words[i] >> shift | words.get(i + 1).map_or(0, |w| w << (64 - shift)) })
+            .collect();
+        while moved.last() == Some(&0) {
+            moved.pop();
+        }
+        let len = (moved.len() - 1) * 64 + 64 - moved.last().unwrap().leading_zeros() as usize;
+        Pots { first: first + lead as i64, len, words: moved }
+    }
 
-            if rule_match {
-                if *does_create_new {
-                    next_gen.insert(pot);
-                }
-                break;
+    // Whether there is a plant i pots after the first, anything outside the row is empty
+    fn bit(&self, i: usize) -> bool {
+        i < self.len && self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    // Every pot can only change from its neighbours, so the next row runs from two before the first
+    // plant to two after the last. The window slides one pot at a time with the newest bit at the bottom.
+    fn step(&self, rules: &Rules) -> Pots {
+        let mut words = vec![0u64; (self.len + 4).div_ceil(64)];
+        let mut window = 0;
+        for i in 0..self.len + 4 {
+            window = (window << 1 | self.bit(i) as usize) & 31;
+            if rules[window] {
+                words[i / 64] |= 1 << (i % 64);
             }
         }
+        Pots::trimmed(self.first - 2, words)
+    }
+
+    fn plants(&self) -> impl Iterator<Item = i64> + '_ {
+        (0..self.len).filter(move |i| self.bit(*i)).map(move |i| self.first + i as i64)
     }
 }
 
-fn main() {
-    let input = include_str!("input");
-    // let input = include_str!("test");
+impl Generation for Pots {
+    type Pattern = Vec<u64>;
 
-    let init_state_reg = Regex::new(r"initial state: (.*)").unwrap();
-    let rules_reg = Regex::new(r"(.*) => (.)").unwrap();
+    fn pattern(&self) -> Vec<u64> {
+        self.words.clone()
+    }
+
+    fn first(&self) -> i64 {
+        self.first
+    }
+
+    fn moved(mut self, by: i64) -> Pots {
+        self.first += by;
+        self
+    }
+}
 
-    let mut current_gen: HashSet<i32> = HashSet::new();
-    let mut rules: Vec<(&str, bool)> = Vec::new();
+// The puzzle's plants on pots, `.` and `#`
+const POTS: [char; 2] = ['.', '#'];
 
+// Rows kept while looking for a cycle before a run gives up, unless the run asks for another limit
+const MAX_STATES: usize = 100_000;
+
+// The initial state and the rules, a pattern missing from the list grows nothing. The endless row
+// outside the initial state is empty unless background_on.
+fn parse(input: &str, background_on: bool) -> Result<(Row, Automaton), String> {
+    let init_state_reg = Regex::new(r"initial state: ([#.]*)").unwrap();
+    let rules_reg = Regex::new(r"^([#.]+) => ([#.])$").unwrap();
+
+    let mut initial = None;
+    let mut rules = Vec::new();
     for line in input.lines() {
         if let Some(c) = init_state_reg.captures(line) {
-            let state = c.get(1).unwrap().as_str();
-            state.chars().enumerate().for_each(|(id, pot)| {
-                if pot == '#' {
-                    current_gen.insert(id as i32);
-                }
-            });
+            initial = Some(c.get(1).unwrap().as_str());
         } else if let Some(c) = rules_reg.captures(line) {
-            rules.push((
-                c.get(1).unwrap().as_str(), 
-                c.get(2).unwrap().as_str().chars().nth(0).unwrap() == '#', 
-                ));
+            rules.push((c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str().chars().next().unwrap()));
+        } else if !line.trim().is_empty() {
+            return Err(format!("can't read '{}'", line));
         }
     }
+    let automaton = Automaton::from_patterns(&POTS, &rules, '.')?;
+    let initial = Row::parse(&automaton, initial.ok_or("no initial state")?, 0, if background_on { '#' } else { '.' })?;
+    Ok((initial, automaton))
+}
+
+// The puzzle automaton as bit rules when it fits, five pots wide and an empty background that stays empty
+fn bit_rules(automaton: &Automaton, initial: &Row) -> Option<Rules> {
+    if automaton.symbols.len() != 2 || automaton.radius != 2 || initial.background != 0 || automaton.table[0] != 0 {
+        return None;
+    }
+    let mut rules = [false; 32];
+    automaton.table.iter().enumerate().for_each(|(i, next)| rules[i] = *next == 1);
+    Some(rules)
+}
 
-    println!("Generation 0: {}", print_state(&current_gen));
-
-    let mut part1sum: i64 = 0;
-    let mut part2sum: i64 = 0;
-
-    let mut last_sum = current_gen.iter().sum::<i32>();
-    let mut last_diff = last_sum as i64;
-    for gen_id in 1.. {
-        let mut next_gen = HashSet::new();
-        populate_next(&mut next_gen, &current_gen, &rules);
-        let sum = next_gen.iter().sum::<i32>();
-        let diff = (sum - last_sum) as i64;
-        // println!("Gen {}: {}: sum {}, diff from last sum {}", gen_id, print_state(&next_gen), sum, diff);
-        if gen_id == 20 {
-            part1sum = next_gen.iter().sum::<i32>() as i64;
+// The row after some generations, with the bit simulation if the rules allow it. Fails when no
+// cycle shows up before max_states rows are stored.
+fn run(initial: &Row, automaton: &Automaton, generations: u64, max_states: usize) -> Result<(Row, Option<Cycle>), String> {
+    match bit_rules(automaton, initial) {
+        Some(rules) => {
+            let pots = Pots::from_plants(initial.first, &initial.cells.iter().map(|cell| *cell == 1).collect::<Vec<_>>());
+            let (pots, cycle) = automaton::generation(&pots, generations, max_states, |pots| pots.step(&rules))?;
+            Ok((Row::new(pots.first, (0..pots.len).map(|i| pots.bit(i) as u8).collect(), 0), cycle))
         }
+        None => automaton::generation(initial, generations, max_states, |row| row.step(automaton)),
+    }
+}
+
+// Sum of the pot numbers with plants, which has no end with plants in the background
+fn plant_sum(row: &Row) -> Option<i64> {
+    if row.background == 1 {
+        return None;
+    }
+    Some(row.cells.iter().enumerate().filter(|(_, cell)| **cell == 1).map(|(i, _)| row.first + i as i64).sum())
+}
 
-        if print_state(&current_gen).eq(&print_state(&next_gen)) && last_diff == diff {
-            println!("Equilibrium reached after generation {}, no more relatively unique generations will happen.", gen_id);
-            part2sum = (sum as i64) + ((50_000_000_000 - gen_id as i64) * diff);
-            break;
+fn main() {
+    // `cargo run -- run file generations [--background-on] [--max-states=N]` runs a puzzle file for any
+    // number of generations, keeping up to N rows (MAX_STATES by default) while looking for a cycle
+    let args: Vec<String> = env::args().collect();
+    let background_on = args.iter().any(|arg| arg == "--background-on");
+    let max_states = args.iter().find_map(|arg| arg.strip_prefix("--max-states="))
+        .map_or(MAX_STATES, |n| n.parse().expect("max states should be a number"));
+    let args: Vec<String> = args.into_iter().filter(|arg| !arg.starts_with("--")).collect();
+    if args.len() == 4 && args[1] == "run" {
+        let input = fs::read_to_string(&args[2]).expect("could not read input");
+        let (initial, automaton) = match parse(&input, background_on) {
+            Ok(parsed) => parsed,
+            Err(e) => { println!("{}: {}", args[2], e); return; }
+        };
+        let (row, cycle) = match run(&initial, &automaton, args[3].parse().expect("generations should be a number"), max_states) {
+            Ok(result) => result,
+            Err(e) => { println!("{}: {}", args[2], e); return; }
+        };
+        if let Some(cycle) = cycle {
+            println!("Generation {} comes back every {} generations moved {}", cycle.start, cycle.period, cycle.shift);
+        }
+        println!("From {} with {} around it: {}", row.first, automaton.symbols[row.background as usize], row.render(&automaton));
+        match plant_sum(&row) {
+            Some(sum) => println!("Sum of pots with plants: {}", sum),
+            None => println!("Every pot far enough out has a plant"),
         }
-        
-        current_gen = next_gen;
-        last_sum = sum;
-        last_diff = diff;
+        return;
+    }
+
+    let (test, test_automaton) = parse(include_str!("test"), false).unwrap();
+    assert!(plant_sum(&run(&test, &test_automaton, 20, MAX_STATES).unwrap().0) == Some(325));
+    // The general engine gets the same rows as the bits
+    let by_rows = automaton::generation(&test, 20, MAX_STATES, |row| row.step(&test_automaton)).unwrap().0;
+    assert!(by_rows == run(&test, &test_automaton, 20, MAX_STATES).unwrap().0 && by_rows.render(&test_automaton) == "#....##....#####...#######....#.#..##");
+
+    // A plant that turns into two and back one pot further on, a cycle of two generations
+    let mut rules = [false; 32];
+    rules[0b00100] = true;
+    rules[0b00010] = true;
+    rules[0b11000] = true;
+    let single = Pots::from_plants(0, &[true]);
+    let (pots, cycle) = automaton::generation(&single, 1_000_000_000, MAX_STATES, |pots| pots.step(&rules)).unwrap();
+    assert!(cycle == Some(Cycle { start: 0, period: 2, shift: 1 }) && pots.plants().collect::<Vec<_>>() == vec![500_000_000]);
+    let (pots, _) = automaton::generation(&single, 1_000_000_001, MAX_STATES, |pots| pots.step(&rules)).unwrap();
+    assert!(pots.plants().collect::<Vec<_>>() == vec![499_999_999, 500_000_000]);
+    // Plants grow next to any plant two pots away, the row grows by two pots on each side every
+    // generation and never repeats, so the run stops at the limit
+    let mut growing = [true; 32];
+    growing[0] = false;
+    let (pots, _) = automaton::generation(&single, 500, 1000, |pots| pots.step(&growing)).unwrap();
+    assert!(pots.first == -1000 && pots.len == 2001);
+    assert!(automaton::generation(&single, 1_000_000_000, 1000, |pots| pots.step(&growing)).is_err());
+
+    // Rows longer than a word, shifted across word boundaries
+    let long: Vec<bool> = (0..150).map(|i| i % 7 == 0 || i % 11 == 3).collect();
+    let pots = Pots::from_plants(-3, &long);
+    assert!(pots.plants().collect::<Vec<_>>() == (0..150).filter(|i| long[*i as usize]).map(|i| i - 3).collect::<Vec<i64>>());
+
+    // Plants growing out of nothing fill the background, which empties again where all five pots have plants
+    let (blinking, automaton) = parse("initial state: #\n\n..... => #\n##### => .\n", false).unwrap();
+    let (row, cycle) = run(&blinking, &automaton, 1, MAX_STATES).unwrap();
+    assert!(row.background == 1 && row.render(&automaton) == "....." && plant_sum(&row).is_none());
+    assert!(cycle.is_none() && run(&blinking, &automaton, 1001, MAX_STATES).unwrap().0.background == 1 && plant_sum(&run(&blinking, &automaton, 1000, MAX_STATES).unwrap().0).is_some());
+    // The puzzle rules in a row full of plants, where "#####" keeps them
+    let (initial, automaton) = parse(include_str!("test"), true).unwrap();
+    assert!(run(&initial, &automaton, 20, MAX_STATES).unwrap().0.background == 0);
+
+    // Wolfram's rule 30 and rule 90, and an additive rule with three states that sums the
+    // neighbourhood mod 3
+    let rule30 = Automaton::wolfram(&POTS, 1, 30).unwrap();
+    let mut row = Row::parse(&rule30, "#", 0, '.').unwrap();
+    let mut rows = Vec::new();
+    for _ in 0..4 {
+        row = row.step(&rule30);
+        rows.push(row.render(&rule30));
+    }
+    assert!(rows == vec!["###", "##..#", "##.####", "##..#...#"]);
+    let rule90 = Automaton::wolfram(&POTS, 1, 90).unwrap();
+    let (row, _) = automaton::generation(&Row::parse(&rule90, "#", 0, '.').unwrap(), 64, MAX_STATES, |row| row.step(&rule90)).unwrap();
+    assert!(row.first == -64 && row.cells.iter().filter(|cell| **cell == 1).count() == 2);
+    // Neither rule ever repeats from a single cell, the 64 generations above keep 65 rows and a run
+    // that can't find a cycle stops at the limit
+    let single = |rule: &Automaton| Row::parse(rule, "#", 0, '.').unwrap();
+    assert!(automaton::generation(&single(&rule90), 64, 65, |row| row.step(&rule90)).is_ok());
+    assert!(automaton::generation(&single(&rule90), 64, 64, |row| row.step(&rule90)).is_err());
+    let stopped = automaton::generation(&single(&rule30), 1_000_000_000, 500, |row| row.step(&rule30));
+    assert!(stopped.err() == Some("no cycle in the first 499 generations, stopped at the limit of 500 stored states".to_string()));
+    let sum_mod_3 = (0..27u32).rev().fold(0u128, |number, i| number * 3 + ((i % 3 + i / 3 % 3 + i / 9) % 3) as u128);
+    let additive = Automaton::wolfram(&['.', 'a', 'b'], 1, sum_mod_3).unwrap();
+    let row = Row::parse(&additive, "a", 0, '.').unwrap().step(&additive).step(&additive);
+    assert!(row.render(&additive) == "ab.ba" && row.first == -2);
+    assert!(Automaton::wolfram(&POTS, 1, 256).is_err() && Automaton::from_patterns(&POTS, &[("..#.", '#')], '.').is_err());
+
+    let (initial, automaton) = parse(include_str!("input"), false).unwrap();
+    println!("Generation 0: {}", initial.render(&automaton));
+
+    let part1sum = plant_sum(&run(&initial, &automaton, 20, MAX_STATES).unwrap().0).unwrap();
+    let (part2, cycle) = run(&initial, &automaton, 50_000_000_000, MAX_STATES).unwrap();
+    let part2sum = plant_sum(&part2).unwrap();
+    let cycle = cycle.unwrap();
+    println!("Generation {} comes back every {} generations moved {} pots", cycle.start, cycle.period, cycle.shift);
+    // The cycle gives the same rows as running every generation
+    let mut stepped = initial.clone();
+    for _ in 0..300 {
+        stepped = stepped.step(&automaton);
     }
+    assert!(run(&initial, &automaton, 300, MAX_STATES).unwrap().0 == stepped);
 
     println!("Part1: sum pots after 20 gens: {}", part1sum);
     println!("Part2: after 50_000_000_000 generations the sum will be: {}", part2sum);
+    assert!(part1sum == 4200 && part2sum == 9699999999321);
 }
@@ -0,0 +1,149 @@
+// What-if analysis: the same battle fought many times with one thing changed, spread over threads.
+//
+// Every variant changes a single thing from the cave and config it starts from: a unit that isn't
+// there, a faction hitting harder or a faction with more hp. The battles are independent so they
+// are split evenly over as many threads as the machine has.
+use std::fmt;
+use std::fmt::Write;
+use std::thread;
+use super::{Battle, BattleConfig, Pos};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Baseline,
+    // The unit starting at pos is left out of the cave
+    Remove { pos: Pos, team: char },
+    Attack { team: char, attack: i32 },
+    Hp { team: char, hp: i32 },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Baseline => write!(f, "baseline"),
+            Change::Remove { pos, team } => write!(f, "without {} at {} {}", team, pos.x, pos.y),
+            Change::Attack { team, attack } => write!(f, "{} attack {}", team, attack),
+            Change::Hp { team, hp } => write!(f, "{} hp {}", team, hp),
+        }
+    }
+}
+
+// How the battle went with one change, survivors has a count for every faction in config order
+#[derive(Debug)]
+pub struct Variant {
+    pub change: Change,
+    pub outcome: Option<i32>,
+    pub rounds: i32,
+    pub hp: i32,
+    pub survivors: Vec<usize>,
+}
+
+// The baseline, every unit removed on its own, every attack power in powers and every hp in hps
+// for each faction
+pub fn what_ifs(input: &str, config: &BattleConfig, powers: &[i32], hps: &[i32]) -> Vec<Change> {
+    let mut changes = vec![Change::Baseline];
+    for (y, line) in input.lines().enumerate() {
+        for (x, c) in line.chars().enumerate() {
+            if config.faction(c).is_some() {
+                changes.push(Change::Remove { pos: Pos::new(x, y), team: c });
+            }
+        }
+    }
+    for faction in &config.factions {
+        changes.extend(powers.iter().map(|&attack| Change::Attack { team: faction.symbol, attack }));
+    }
+    for faction in &config.factions {
+        changes.extend(hps.iter().map(|&hp| Change::Hp { team: faction.symbol, hp }));
+    }
+    changes
+}
+
+fn fight(input: &str, config: &BattleConfig, change: &Change) -> Variant {
+    let mut config = config.clone();
+    let mut input = input.to_string();
+    match change {
+        Change::Baseline => {}
+        Change::Remove { pos, .. } => {
+            input = input.lines().enumerate()
+                .map(|(y, line)| line.chars().enumerate().map(|(x, c)| if x == pos.x && y == pos.y { '.' } else { c }).collect::<String>() + "\n")
+                .collect();
+        }
+        Change::Attack { team, attack } => config.factions.iter_mut().filter(|f| f.symbol == *team).for_each(|f| f.attack = *attack),
+        Change::Hp { team, hp } => config.factions.iter_mut().filter(|f| f.symbol == *team).for_each(|f| f.hp = *hp),
+    }
+
+    // Leaving out a unit or changing a faction keeps a cave that was accepted before
+    let mut battle = Battle::new(&input, &config).expect("cave was checked");
+    while !battle.is_over() {
+        battle.step_round();
+    }
+    Variant {
+        change: change.clone(),
+        outcome: battle.outcome(),
+        rounds: battle.rounds,
+        hp: battle.alive().map(|unit| unit.hp).sum(),
+        survivors: config.factions.iter().map(|f| battle.alive_in(f.symbol)).collect(),
+    }
+}
+
+// Fights every change, the variants come back in the same order as the changes
+pub fn analyze(input: &str, config: &BattleConfig, changes: &[Change]) -> Vec<Variant> {
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk = changes.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = changes.chunks(chunk)
+            .map(|changes| scope.spawn(move || changes.iter().map(|change| fight(input, config, change)).collect::<Vec<_>>()))
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    })
+}
+
+// The removal of a unit from another faction that leaves the most of team alive, the earliest one on ties
+pub fn best_removal<'a>(variants: &'a [Variant], config: &BattleConfig, team: char) -> Option<&'a Variant> {
+    let index = config.factions.iter().position(|f| f.symbol == team)?;
+    variants.iter()
+        .filter(|v| matches!(v.change, Change::Remove { team: removed, .. } if removed != team))
+        .fold(None, |best: Option<&'a Variant>, v| match best {
+            Some(best) if best.survivors[index] >= v.survivors[index] => Some(best),
+            _ => Some(v),
+        })
+}
+
+pub fn print_table(variants: &[Variant], config: &BattleConfig) {
+    print!("{:<20} {:>8} {:>7} {:>6}", "change", "outcome", "rounds", "hp");
+    for faction in &config.factions {
+        print!(" {:>8}", faction.name);
+    }
+    println!();
+    for v in variants {
+        let outcome = v.outcome.map(|o| o.to_string()).unwrap_or("stalemate".to_string());
+        print!("{:<20} {:>8} {:>7} {:>6}", v.change.to_string(), outcome, v.rounds, v.hp);
+        for survivors in &v.survivors {
+            print!(" {:>8}", survivors);
+        }
+        println!();
+    }
+}
+
+// A text field of the csv, always quoted so names with commas or quotes stay one field
+fn csv_field(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+// One line per variant with the survivors of each faction in a column named after it, the
+// outcome is left empty for a stalemate
+pub fn to_csv(variants: &[Variant], config: &BattleConfig) -> String {
+    let mut csv = ["change", "outcome", "rounds", "hp"].iter().map(|name| csv_field(name)).collect::<Vec<_>>().join(",");
+    for faction in &config.factions {
+        write!(csv, ",{}", csv_field(&faction.name)).unwrap();
+    }
+    csv.push('\n');
+    for v in variants {
+        write!(csv, "{},{},{},{}", csv_field(&v.change.to_string()), v.outcome.map(|o| o.to_string()).unwrap_or_default(), v.rounds, v.hp).unwrap();
+        for survivors in &v.survivors {
+            write!(csv, ",{}", survivors).unwrap();
+        }
+        csv.push('\n');
+    }
+    csv
+}
@@ -0,0 +1,167 @@
+// What happens in a battle, written as one JSON object per line.
+//
+// A log starts with the cave without units, the factions and every unit where it starts, so a
+// replay doesn't need anything else. Positions are written as x and y fields.
+use std::collections::HashMap;
+use std::fmt::Write;
+use super::Pos;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Cave { rows: Vec<String> },
+    Faction { symbol: char, name: String, hp: i32, attack: i32 },
+    Unit { pos: Pos, team: char },
+    RoundStart { round: i32 },
+    Moved { from: Pos, to: Pos },
+    Attack { attacker: Pos, target: Pos, damage: i32, hp: i32 },
+    Died { pos: Pos, team: char },
+    // A trap or spring changed the hp of the unit standing on it
+    Tile { pos: Pos, hp: i32 },
+    Ended { rounds: i32, hp: i32, outcome: i32 },
+    // The units left can't get at each other, the battle stops without an outcome
+    Stalemate { rounds: i32 },
+}
+
+#[derive(Debug)]
+enum Json {
+    Number(i64),
+    Text(String),
+    List(Vec<String>),
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Event {
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let pos = |name: &str, p: &Pos| format!("\"{}x\":{},\"{}y\":{}", name, p.x, name, p.y);
+        match self {
+            Event::Cave { rows } => {
+                let rows: Vec<String> = rows.iter().map(|r| quote(r)).collect();
+                write!(out, "{{\"event\":\"cave\",\"rows\":[{}]}}", rows.join(","))
+            }
+            Event::Faction { symbol, name, hp, attack } => write!(
+                out,
+                "{{\"event\":\"faction\",\"symbol\":{},\"name\":{},\"hp\":{},\"attack\":{}}}",
+                quote(&symbol.to_string()), quote(name), hp, attack
+            ),
+            Event::Unit { pos: p, team } => write!(out, "{{\"event\":\"unit\",{},\"team\":{}}}", pos("", p), quote(&team.to_string())),
+            Event::RoundStart { round } => write!(out, "{{\"event\":\"round\",\"round\":{}}}", round),
+            Event::Moved { from, to } => write!(out, "{{\"event\":\"moved\",{},{}}}", pos("", from), pos("to_", to)),
+            Event::Attack { attacker, target, damage, hp } => write!(
+                out,
+                "{{\"event\":\"attack\",{},{},\"damage\":{},\"hp\":{}}}",
+                pos("", attacker), pos("target_", target), damage, hp
+            ),
+            Event::Died { pos: p, team } => write!(out, "{{\"event\":\"died\",{},\"team\":{}}}", pos("", p), quote(&team.to_string())),
+            Event::Tile { pos: p, hp } => write!(out, "{{\"event\":\"tile\",{},\"hp\":{}}}", pos("", p), hp),
+            Event::Ended { rounds, hp, outcome } => {
+                write!(out, "{{\"event\":\"ended\",\"rounds\":{},\"hp\":{},\"outcome\":{}}}", rounds, hp, outcome)
+            }
+            Event::Stalemate { rounds } => write!(out, "{{\"event\":\"stalemate\",\"rounds\":{}}}", rounds),
+        }
+        .unwrap();
+        out
+    }
+
+    // Reads back a line written by to_json
+    pub fn parse(line: &str) -> Result<Event, String> {
+        let fields = parse_object(line)?;
+        let number = |name: &str| match fields.get(name) {
+            Some(Json::Number(n)) => Ok(*n),
+            _ => Err(format!("expected a number '{}' in {}", name, line)),
+        };
+        let text = |name: &str| match fields.get(name) {
+            Some(Json::Text(t)) => Ok(t.clone()),
+            _ => Err(format!("expected a string '{}' in {}", name, line)),
+        };
+        let symbol = |name: &str| text(name)?.chars().next().ok_or(format!("empty '{}' in {}", name, line));
+        let pos = |prefix: &str| Ok::<Pos, String>(Pos::new(number(&format!("{}x", prefix))? as usize, number(&format!("{}y", prefix))? as usize));
+
+        match text("event")?.as_str() {
+            "cave" => match fields.get("rows") {
+                Some(Json::List(rows)) => Ok(Event::Cave { rows: rows.clone() }),
+                _ => Err(format!("expected a list of rows in {}", line)),
+            },
+            "faction" => Ok(Event::Faction { symbol: symbol("symbol")?, name: text("name")?, hp: number("hp")? as i32, attack: number("attack")? as i32 }),
+            "unit" => Ok(Event::Unit { pos: pos("")?, team: symbol("team")? }),
+            "round" => Ok(Event::RoundStart { round: number("round")? as i32 }),
+            "moved" => Ok(Event::Moved { from: pos("")?, to: pos("to_")? }),
+            "attack" => Ok(Event::Attack { attacker: pos("")?, target: pos("target_")?, damage: number("damage")? as i32, hp: number("hp")? as i32 }),
+            "died" => Ok(Event::Died { pos: pos("")?, team: symbol("team")? }),
+            "tile" => Ok(Event::Tile { pos: pos("")?, hp: number("hp")? as i32 }),
+            "ended" => Ok(Event::Ended { rounds: number("rounds")? as i32, hp: number("hp")? as i32, outcome: number("outcome")? as i32 }),
+            "stalemate" => Ok(Event::Stalemate { rounds: number("rounds")? as i32 }),
+            other => Err(format!("unknown event '{}'", other)),
+        }
+    }
+}
+
+// Just enough JSON for the logs: a flat object of numbers, strings and lists of strings
+fn parse_object(line: &str) -> Result<HashMap<String, Json>, String> {
+    let chars: Vec<char> = line.trim().chars().collect();
+    let mut pos = 0;
+    let mut fields = HashMap::new();
+    let expect = |pos: &mut usize, c: char| {
+        if chars.get(*pos) == Some(&c) {
+            *pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at column {} of {}", c, *pos + 1, line))
+        }
+    };
+    let string = |pos: &mut usize| {
+        expect(pos, '"')?;
+        let mut s = String::new();
+        loop {
+            match chars.get(*pos) {
+                Some('"') => break,
+                Some('\\') => {
+                    *pos += 1;
+                    s.push(*chars.get(*pos).ok_or("line ends in a string")?);
+                }
+                Some(c) => s.push(*c),
+                None => return Err("line ends in a string".to_string()),
+            }
+            *pos += 1;
+        }
+        *pos += 1;
+        Ok(s)
+    };
+
+    expect(&mut pos, '{')?;
+    while chars.get(pos) != Some(&'}') {
+        if !fields.is_empty() {
+            expect(&mut pos, ',')?;
+        }
+        let name = string(&mut pos)?;
+        expect(&mut pos, ':')?;
+        let value = match chars.get(pos) {
+            Some('"') => Json::Text(string(&mut pos)?),
+            Some('[') => {
+                pos += 1;
+                let mut list = Vec::new();
+                while chars.get(pos) != Some(&']') {
+                    if !list.is_empty() {
+                        expect(&mut pos, ',')?;
+                    }
+                    list.push(string(&mut pos)?);
+                }
+                pos += 1;
+                Json::List(list)
+            }
+            _ => {
+                let start = pos;
+                while chars.get(pos).is_some_and(|c| *c == '-' || c.is_ascii_digit()) {
+                    pos += 1;
+                }
+                let digits: String = chars[start..pos].iter().collect();
+                Json::Number(digits.parse().map_err(|_| format!("expected a value for '{}' in {}", name, line))?)
+            }
+        };
+        fields.insert(name, value);
+    }
+    Ok(fields)
+}
@@ -1,13 +1,12 @@
-use std::cmp::Ordering;
-use std::collections::{HashSet, HashMap, BinaryHeap};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet, HashMap};
+use std::env;
+use std::fs;
 
-fn reading_order(a: &Pos, b: &Pos) -> std::cmp::Ordering {
-    if a.y == b.y {
-        return a.x.partial_cmp(&b.x).unwrap();
-    } else {
-        return a.y.partial_cmp(&b.y).unwrap();
-    }
-}
+mod analysis;
+mod events;
+
+use events::Event;
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 struct Pos {
@@ -18,316 +17,785 @@ struct Pos {
 impl Pos {
     fn new(x: usize, y: usize) -> Pos {
         Pos {
-            x: x,
-            y: y
+            x,
+            y
         }
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
-struct Unit {
-    pos: Pos,
+#[derive(Debug, Clone)]
+struct Faction {
+    symbol: char,
+    name: String,
     hp: i32,
-    team: char,
-    acted: bool
+    attack: i32,
+}
+
+// What the cave floor can be besides plain floor, each with its map symbol
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tile {
+    Floor,
+    Wall,
+    // '~', takes water_cost turns to wade into
+    Water,
+    // '^', hurts a unit ending its turn on it by trap_damage
+    Trap,
+    // '+', heals a unit ending its turn on it by spring_heal, up to its starting hp
+    Spring,
 }
 
-impl Unit {
-    fn new(x: usize, y: usize, team: char) -> Unit {
-        Unit {
-            pos: Pos::new(x, y),
-            hp: 200,
-            team: team,
-            acted: false
+impl Tile {
+    fn from_symbol(c: char) -> Option<Tile> {
+        match c {
+            '.' => Some(Tile::Floor),
+            '#' => Some(Tile::Wall),
+            '~' => Some(Tile::Water),
+            '^' => Some(Tile::Trap),
+            '+' => Some(Tile::Spring),
+            _ => None,
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Tile::Floor => ' ',
+            Tile::Wall => '#',
+            Tile::Water => '~',
+            Tile::Trap => '^',
+            Tile::Spring => '+',
         }
     }
 }
 
-fn print_map(walls: &HashSet<Pos>, all_units: &HashMap<Pos, Unit>) {
-    let left_x = walls.iter().min_by_key(|v| v.x).unwrap().x;
-    let right_x = walls.iter().max_by_key(|v| v.x).unwrap().x;
-    let top_y = walls.iter().min_by_key(|v| v.y).unwrap().y;
-    let bottom_y = walls.iter().max_by_key(|v| v.y).unwrap().y;
-    for y in top_y..=bottom_y {
-        print!("{:02} ", y);
-        let mut ghp = Vec::new();
-        let mut ehp = Vec::new();
-        for x in left_x..=right_x {
-            if walls.contains(&Pos::new(x, y)) {
-                print!("#");
-            } else if let Some(unit) = all_units.get(&Pos::new(x, y)) {
-                print!("{}", unit.team);
-                if unit.team == 'G' {
-                    ghp.push(unit.hp);
-                } else {
-                    ehp.push(unit.hp);
+// The factions in a battle and who fights whom, a faction hostile to itself is a free-for-all
+#[derive(Debug, Clone)]
+struct BattleConfig {
+    factions: Vec<Faction>,
+    hostile: HashSet<(char, char)>,
+    water_cost: u32,
+    trap_damage: i32,
+    spring_heal: i32,
+}
+
+impl BattleConfig {
+    fn empty() -> BattleConfig {
+        BattleConfig { factions: Vec::new(), hostile: HashSet::new(), water_cost: 2, trap_damage: 10, spring_heal: 10 }
+    }
+
+    // Elves and goblins with 200 hp each, goblins hit for 3
+    fn puzzle(elf_power: i32) -> BattleConfig {
+        let mut config = BattleConfig::empty();
+        config.hostile.insert(('E', 'G'));
+        config.hostile.insert(('G', 'E'));
+        config.factions = vec![
+            Faction { symbol: 'E', name: "Elfs".to_string(), hp: 200, attack: elf_power },
+            Faction { symbol: 'G', name: "Goblins".to_string(), hp: 200, attack: 3 },
+        ];
+        config
+    }
+
+    // One definition per line:
+    //   faction E Elfs 200 3   map symbol, name, starting hp and attack power
+    //   hostile E G O          every listed faction fights every other listed one, `hostile X X`
+    //                          makes the units of X fight each other
+    //   water 2                turns it takes to wade into water, default 2
+    //   trap 10                damage from ending a turn on a trap, default 10
+    //   spring 10              healing from ending a turn on a spring, default 10
+    fn parse(input: &str) -> Result<BattleConfig, String> {
+        let mut config = BattleConfig::empty();
+        for (i, line) in input.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let value = |word: &str| word.parse::<i32>().map_err(|_| format!("line {}: bad value '{}'", i + 1, word));
+            match words.first() {
+                None => {}
+                Some(&"water") if words.len() == 2 && value(words[1])? >= 1 => config.water_cost = value(words[1])? as u32,
+                // Negative damage would heal and a negative spring would hurt, that's the other tile
+                Some(&"trap") | Some(&"spring") if words.len() == 2 && value(words[1])? < 0 => {
+                    return Err(format!("line {}: {} needs a value of at least 0", i + 1, words[0]));
                 }
-            } else {
-                print!(" ");
+                Some(&"trap") if words.len() == 2 => config.trap_damage = value(words[1])?,
+                Some(&"spring") if words.len() == 2 => config.spring_heal = value(words[1])?,
+                Some(&"faction") if words.len() == 5 => {
+                    let mut symbol = words[1].chars();
+                    let faction = Faction {
+                        symbol: symbol.next().unwrap(),
+                        name: words[2].to_string(),
+                        hp: words[3].parse().map_err(|_| format!("line {}: bad hp '{}'", i + 1, words[3]))?,
+                        attack: words[4].parse().map_err(|_| format!("line {}: bad attack power '{}'", i + 1, words[4]))?,
+                    };
+                    if symbol.next().is_some() || Tile::from_symbol(faction.symbol).is_some() {
+                        return Err(format!("line {}: '{}' can't be a map symbol", i + 1, words[1]));
+                    }
+                    // A unit without hp would be dead from the start and one without attack power never kills
+                    if faction.hp < 1 || faction.attack < 1 {
+                        return Err(format!("line {}: faction {} needs hp and attack power of at least 1", i + 1, faction.symbol));
+                    }
+                    if config.faction(faction.symbol).is_some() {
+                        return Err(format!("line {}: faction {} defined twice", i + 1, faction.symbol));
+                    }
+                    config.factions.push(faction);
+                }
+                Some(&"hostile") if words.len() >= 3 => {
+                    for a in &words[1..] {
+                        for b in &words[1..] {
+                            if a != b || words[1..].iter().filter(|w| w == &a).count() > 1 {
+                                config.hostile.insert((a.chars().next().unwrap(), b.chars().next().unwrap()));
+                            }
+                        }
+                    }
+                }
+                _ => return Err(format!("line {}: expected 'faction symbol name hp attack', 'hostile symbols...' or water/trap/spring value", i + 1)),
             }
         }
-        if ghp.len() > 0 {
-            print!(" Goblins {:?}", ghp);
+        if let Some((a, b)) = config.hostile.iter().find(|(a, b)| config.faction(*a).is_none() || config.faction(*b).is_none()) {
+            return Err(format!("hostile {} {} names an unknown faction", a, b));
         }
-        if ehp.len() > 0 {
-            print!(" Elfs {:?}", ehp);
-        }
-        println!();
+        Ok(config)
     }
-}
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-struct DistState {
-    x: usize,
-    y: usize,
-    cost: u32
-}
+    fn faction(&self, symbol: char) -> Option<&Faction> {
+        self.factions.iter().find(|f| f.symbol == symbol)
+    }
 
-impl Ord for DistState {
-    fn cmp(&self, other: &DistState) -> Ordering {
-        other.cost.cmp(&self.cost)
-            .then_with(|| {
-                if self.y == other.y {
-                    other.x.cmp(&self.x)
-                } else {
-                    other.y.cmp(&self.y)
-                }
-            })
+    fn hostile(&self, attacker: char, defender: char) -> bool {
+        self.hostile.contains(&(attacker, defender))
     }
 }
 
-impl PartialOrd for DistState {
-    fn partial_cmp(&self, other: &DistState) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+type UnitId = usize;
+
+// Battles still going after this many rounds are called a stalemate, in case units keep moving
+// around without ever getting at each other
+const ROUND_LIMIT: i32 = 1000;
+
+// How a battle ended
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    // Full rounds times the hp left, once no two units left are hostile
+    Won(i32),
+    // A unit of the protected faction died and the battle was called off
+    Lost,
+    // Hostile units are left that can't get at each other
+    Stalemate,
 }
 
-fn distance(from: &Pos, to: &Pos, walls: &HashSet<Pos>, other_units: &HashMap<Pos, Unit>) -> Option<u32> {
-    let mut visited = HashSet::new();
-    let mut to_visit = BinaryHeap::new();
-    to_visit.push(DistState {x: from.x, y: from.y, cost: 0u32});
+#[derive(Debug, Clone)]
+struct Unit {
+    id: UnitId,
+    cell: usize,
+    hp: i32,
+    team: char,
+    // Turns left wading into water, it can attack but not move until then
+    wading: u32,
+}
 
-    while let Some(state) = to_visit.pop() {
-        let curr_pos = Pos::new(state.x, state.y);
-        if visited.contains(&curr_pos) {
-            continue;
-        } else {
-            visited.insert(curr_pos.clone());
+// A battle in progress. The cave is a grid of cells, cell y * width + x so cells sort in reading
+// order, with the tile and the unit standing there for each. Units are indexed by their id and stay in units after dying.
+struct Battle {
+    config: BattleConfig,
+    width: usize,
+    tiles: Vec<Tile>,
+    occupant: Vec<Option<UnitId>>,
+    units: Vec<Unit>,
+    // Full rounds done, counting the last one only if it was finished
+    rounds: i32,
+    over: bool,
+    // Over without a winner, nothing changed in a whole round or it went on for ROUND_LIMIT rounds
+    stalemate: bool,
+    events: Vec<Event>,
+}
+
+impl Battle {
+    // Takes the units from the cave, ids are in reading order
+    fn new(input: &str, config: &BattleConfig) -> Result<Battle, String> {
+        let rows: Vec<String> = input.lines()
+            .map(|line| line.chars().map(|c| if config.faction(c).is_some() { '.' } else { c }).collect())
+            .collect();
+        let mut battle = Battle::from_cave(&rows, config.clone())?;
+        for faction in &config.factions {
+            battle.events.push(Event::Faction { symbol: faction.symbol, name: faction.name.clone(), hp: faction.hp, attack: faction.attack });
         }
-        if walls.contains(&curr_pos) || other_units.contains_key(&curr_pos) {
-            continue;
+        for (y, line) in input.lines().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                if config.faction(c).is_some() {
+                    battle.add_unit(&Pos::new(x, y), c);
+                }
+            }
         }
+        battle.over = !battle.fighting();
+        Ok(battle)
+    }
 
-        if &curr_pos == to {
-            return Some(state.cost);
+    // An empty cave, everything outside the rows counts as wall. Symbols that are neither a tile
+    // nor a faction in the config are an error, and so is anything but wall on the edge since
+    // adjacent() doesn't look past it.
+    fn from_cave(rows: &[String], config: BattleConfig) -> Result<Battle, String> {
+        let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+        let mut tiles = vec![Tile::Wall; width * rows.len()];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let tile = Tile::from_symbol(c).ok_or(format!("unknown map symbol '{}' at {},{}", c, x, y))?;
+                if tile != Tile::Wall && (x == 0 || y == 0 || x == width - 1 || y == rows.len() - 1) {
+                    return Err(format!("the cave needs a wall all around, '{}' at {},{}", c, x, y));
+                }
+                tiles[y * width + x] = tile;
+            }
         }
+        Ok(Battle {
+            config,
+            width,
+            occupant: vec![None; tiles.len()],
+            tiles,
+            units: Vec::new(),
+            rounds: 0,
+            over: false,
+            stalemate: false,
+            events: vec![Event::Cave { rows: rows.to_vec() }],
+        })
+    }
 
-        // Add candidates
-        to_visit.push(DistState { x: curr_pos.x - 1, y: curr_pos.y, cost: state.cost + 1 });
-        to_visit.push(DistState { x: curr_pos.x + 1, y: curr_pos.y, cost: state.cost + 1 });
-        to_visit.push(DistState { x: curr_pos.x, y: curr_pos.y - 1, cost: state.cost + 1 });
-        to_visit.push(DistState { x: curr_pos.x, y: curr_pos.y + 1, cost: state.cost + 1 });
+    fn add_unit(&mut self, pos: &Pos, team: char) {
+        let cell = pos.y * self.width + pos.x;
+        let id = self.units.len();
+        self.units.push(Unit { id, cell, hp: self.config.faction(team).unwrap().hp, team, wading: 0 });
+        self.occupant[cell] = Some(id);
+        self.events.push(Event::Unit { pos: pos.clone(), team });
     }
 
-    return None;
-}
+    fn pos(&self, cell: usize) -> Pos {
+        Pos::new(cell % self.width, cell / self.width)
+    }
 
-fn bad_guy_in_range(good_guy: &Unit, all_units: &HashMap<Pos, Unit>) -> Option<Unit> {
-    // Manually sorted in reading order
-    let adjacent = vec![
-        Pos::new(good_guy.pos.x, good_guy.pos.y - 1),
-        Pos::new(good_guy.pos.x - 1, good_guy.pos.y),
-        Pos::new(good_guy.pos.x + 1, good_guy.pos.y),
-        Pos::new(good_guy.pos.x, good_guy.pos.y + 1),
-        ];
-    let mut lowest_bad_guy = None;
-    let mut lowest_hp = 201;
-    for adj in adjacent {
-        if let Some(unit) = all_units.get(&adj) {
-            if unit.team != good_guy.team {
-                if unit.hp < lowest_hp {
-                    lowest_hp = unit.hp;
-                    lowest_bad_guy = Some(unit.clone());
-                }
+    fn cell(&self, pos: &Pos) -> Option<usize> {
+        if pos.x < self.width && pos.y * self.width < self.tiles.len() { Some(pos.y * self.width + pos.x) } else { None }
+    }
+
+    // Manually sorted in reading order, from_cave makes sure the cave has walls all around so these stay inside
+    fn adjacent(&self, cell: usize) -> [usize; 4] {
+        [cell - self.width, cell - 1, cell + 1, cell + self.width]
+    }
+
+    fn is_open(&self, cell: usize) -> bool {
+        self.tiles[cell] != Tile::Wall && self.occupant[cell].is_none()
+    }
+
+    fn alive(&self) -> impl Iterator<Item = &Unit> {
+        self.units.iter().filter(|unit| unit.hp > 0)
+    }
+
+    fn alive_in(&self, team: char) -> usize {
+        self.alive().filter(|unit| unit.team == team).count()
+    }
+
+    fn fighting(&self) -> bool {
+        self.alive().any(|a| self.alive().any(|b| a.id != b.id && self.config.hostile(a.team, b.team)))
+    }
+
+    fn is_over(&self) -> bool {
+        self.over
+    }
+
+    // Rounds times the hp left, once the battle is over with a winner
+    fn outcome(&self) -> Option<i32> {
+        if self.over && !self.stalemate { Some(self.rounds * self.alive().map(|unit| unit.hp).sum::<i32>()) } else { None }
+    }
+
+    fn end_in_stalemate(&mut self) {
+        self.over = true;
+        self.stalemate = true;
+        self.events.push(Event::Stalemate { rounds: self.rounds });
+    }
+
+    // Every unit alive at the start of the round takes its turn in reading order, unless it dies
+    // before that. A round where no unit moved, waded, attacked or was changed by a tile would
+    // repeat forever, so the battle ends in a stalemate.
+    fn step_round(&mut self) {
+        if self.over {
+            return;
+        }
+        if self.rounds >= ROUND_LIMIT {
+            self.end_in_stalemate();
+            return;
+        }
+        let before: Vec<(usize, i32, u32)> = self.units.iter().map(|unit| (unit.cell, unit.hp, unit.wading)).collect();
+        self.events.push(Event::RoundStart { round: self.rounds });
+        let mut order: Vec<UnitId> = self.alive().map(|unit| unit.id).collect();
+        order.sort_by_key(|id| self.units[*id].cell);
+        for (i, id) in order.iter().enumerate() {
+            if self.units[*id].hp <= 0 {
+                continue;
             }
+            if self.take_turn(*id) && !self.fighting() {
+                // The round only counts when the last unit in the order ended it
+                let completed_this = if i == order.len() - 1 { 1 } else { 0 };
+                self.rounds += completed_this;
+                self.over = true;
+                let hp = self.alive().map(|unit| unit.hp).sum();
+                self.events.push(Event::Ended { rounds: self.rounds, hp, outcome: self.rounds * hp });
+                return;
+            }
+        }
+        self.rounds += 1;
+        if self.units.iter().map(|unit| (unit.cell, unit.hp, unit.wading)).eq(before) {
+            self.end_in_stalemate();
         }
     }
-    lowest_bad_guy
-}
 
-fn try_attack(attacker: &Unit, all_units: &mut HashMap<Pos, Unit>, elf_power: i32) -> Option<Pos> {
-    if let Some(mut bad_guy) = bad_guy_in_range(&attacker, &all_units) {
-        bad_guy.hp -= if attacker.team == 'E' { elf_power } else { 3 };   // Do damage
-        // println!("{:?}:{} attacked {:?}:{}", attacker, attacker.hp, bad_guy.pos, bad_guy.hp);
-        if bad_guy.hp <= 0 {
-            // println!("Bad guy at {:?} died", bad_guy.pos);
-            all_units.remove(&bad_guy.pos);
-        } else {
-            all_units.insert(bad_guy.pos.clone(), bad_guy.clone());
+    // Attacks an enemy next to the unit, or moves towards the closest one and attacks if it got
+    // there, then the tile it ends on has its effect. Returns true if someone died.
+    fn take_turn(&mut self, id: UnitId) -> bool {
+        let killed = match self.try_attack(id) {
+            Some(killed) => killed,
+            None if self.units[id].wading > 0 => {
+                self.units[id].wading -= 1;
+                false
+            }
+            None => self.try_move(id),
+        };
+        self.tile_effect(id) || killed
+    }
+
+    // Returns true if the unit got next to an enemy after moving and killed it
+    fn try_move(&mut self, id: UnitId) -> bool {
+        let team = self.units[id].team;
+        let targets: Vec<usize> = self.alive()
+            .filter(|unit| unit.id != id && self.config.hostile(team, unit.team))
+            .flat_map(|enemy| self.adjacent(enemy.cell).to_vec())
+            .filter(|cell| self.is_open(*cell))
+            .collect();
+        if targets.is_empty() {
+            return false;
+        }
+
+        // Towards the closest target and first in reading order of those
+        let reached = self.reachable(self.units[id].cell);
+        let best = targets.iter().filter_map(|target| reached[*target].map(|(dist, step)| (dist, *target, step))).min();
+        if let Some((_, _, step)) = best {
+            let from = self.units[id].cell;
+            self.events.push(Event::Moved { from: self.pos(from), to: self.pos(step) });
+            self.move_unit(id, step);
+            self.units[id].wading = self.cost(step) - 1;
+            return self.try_attack(id).unwrap_or(false);   // Attack directly after moving
         }
-        return Some(bad_guy.pos.clone());
+        false
     }
-    None
-}
 
-fn perform_unit(good_guy_pos: &Pos, 
-    all_units: &mut HashMap<Pos, Unit>, 
-    walls: &HashSet<Pos>,
-    elf_power: i32) -> Option<Pos> {
-    if !all_units.contains_key(&good_guy_pos) {
-        // println!("Pos {:?} does no longer exist in {:?}, they likely already died", good_guy_pos, all_units);
-        return None;
-    }
-    let mut good_guy = all_units.get(&good_guy_pos).expect("unit should exist").clone();
-    all_units.remove(&good_guy.pos);
-
-    // Manually sorted adjacent in reading order
-    let adjacent = vec![
-        Pos::new(good_guy.pos.x, good_guy.pos.y - 1),
-        Pos::new(good_guy.pos.x - 1, good_guy.pos.y),
-        Pos::new(good_guy.pos.x + 1, good_guy.pos.y),
-        Pos::new(good_guy.pos.x, good_guy.pos.y + 1),
-        ];
+    // Turns it takes to step into a cell
+    fn cost(&self, cell: usize) -> u32 {
+        if self.tiles[cell] == Tile::Water { self.config.water_cost } else { 1 }
+    }
+
+    // Traps and springs act on the unit ending its turn on them, returns true if the unit died
+    fn tile_effect(&mut self, id: UnitId) -> bool {
+        let unit = &self.units[id];
+        let max_hp = self.config.faction(unit.team).unwrap().hp;
+        let hp = match self.tiles[unit.cell] {
+            Tile::Trap => unit.hp - self.config.trap_damage,
+            Tile::Spring => (unit.hp + self.config.spring_heal).min(max_hp.max(unit.hp)),
+            _ => return false,
+        };
+        if hp == unit.hp || unit.hp <= 0 {
+            return false;
+        }
+        let pos = self.pos(unit.cell);
+        self.units[id].hp = hp;
+        self.events.push(Event::Tile { pos: pos.clone(), hp });
+        if hp <= 0 {
+            self.occupant[self.units[id].cell] = None;
+            self.events.push(Event::Died { pos, team: self.units[id].team });
+        }
+        hp <= 0
+    }
+
+    fn move_unit(&mut self, id: UnitId, to: usize) {
+        self.occupant[self.units[id].cell] = None;
+        self.occupant[to] = Some(id);
+        self.units[id].cell = to;
+    }
 
-    // Populate target positions
-    let mut target_pos = Vec::new();
-    all_units.iter()
-        .map(|(pos, unit)| unit)
-        .filter(|unit| unit.team != good_guy.team)
-        .for_each(|bad_guy| {
-            target_pos.push(Pos::new(bad_guy.pos.x - 1, bad_guy.pos.y));
-            target_pos.push(Pos::new(bad_guy.pos.x + 1, bad_guy.pos.y));
-            target_pos.push(Pos::new(bad_guy.pos.x, bad_guy.pos.y - 1));
-            target_pos.push(Pos::new(bad_guy.pos.x, bad_guy.pos.y + 1));
-        });
-    target_pos = target_pos.into_iter()
-        .filter(|p| !walls.contains(&p) && !all_units.contains_key(&p))
-        .collect();
-
-    all_units.remove(&good_guy.pos);
-
-    // Can we attack someone?
-    let mut attacked = try_attack(&good_guy, all_units, elf_power);
-    if attacked.is_none() && target_pos.len() > 0 {
-        // Try to move instead
-        let mut moves: Vec<(&Pos, &Pos, u32)> = adjacent.iter()
-            .map(|adj| {
-                let mut targets: Vec<(&Pos, u32)> = target_pos.iter().map(|p| (p, distance(&adj, &p, &walls, &all_units)))
-                    .filter(|(p, dist)| dist.is_some())
-                    .map(|(p, dist)| (p, dist.unwrap()))
-                    .collect();
-                targets.sort_by(|a, b| {
-                    // Sort targets by distance, then on reading order
-                    if a.1 != b.1 {
-                        a.1.partial_cmp(&b.1).unwrap()
-                    } else {
-                        reading_order(a.0, b.0)
+    // Hits the weakest enemy next to the unit, the first in reading order if several are as weak.
+    // None if there was nobody to hit, else whether the enemy died.
+    fn try_attack(&mut self, id: UnitId) -> Option<bool> {
+        let attacker = &self.units[id];
+        let target = self.adjacent(attacker.cell).iter()
+            .filter_map(|cell| self.occupant[*cell])
+            .filter(|other| self.config.hostile(attacker.team, self.units[*other].team))
+            .min_by_key(|other| self.units[*other].hp)?;
+        let damage = self.config.faction(attacker.team).unwrap().attack;
+        let (attacker_pos, target_pos) = (self.pos(attacker.cell), self.pos(self.units[target].cell));
+        self.units[target].hp -= damage;
+        let hp = self.units[target].hp;
+        self.events.push(Event::Attack { attacker: attacker_pos, target: target_pos.clone(), damage, hp });
+        if hp <= 0 {
+            self.occupant[self.units[target].cell] = None;
+            self.events.push(Event::Died { pos: target_pos, team: self.units[target].team });
+        }
+        Some(hp <= 0)
+    }
+
+    // Shortest paths from a cell over the open cells, stepping into a cell costs the turns it
+    // takes, giving the cost of reaching every reachable cell and the first step towards it.
+    // When there are several cheapest paths the first step is the first one in reading order, a
+    // cell takes the best first step of all its neighbours on a cheapest path, and those are final
+    // since every cost is at least 1 so they are all taken from the heap before the cell is.
+    fn reachable(&self, from: usize) -> Vec<Option<(u32, usize)>> {
+        let mut reached: Vec<Option<(u32, usize)>> = vec![None; self.tiles.len()];
+        let mut done = vec![false; self.tiles.len()];
+        let mut to_visit = BinaryHeap::new();
+        to_visit.push(Reverse((0u32, from)));
+
+        while let Some(Reverse((dist, cell))) = to_visit.pop() {
+            if done[cell] {
+                continue;
+            }
+            done[cell] = true;
+            for adj in self.adjacent(cell).iter() {
+                if !self.is_open(*adj) || *adj == from {
+                    continue;
+                }
+                let step = if cell == from { *adj } else { reached[cell].unwrap().1 };
+                let adj_dist = dist + self.cost(*adj);
+                match reached[*adj] {
+                    Some((found_dist, found_step)) if found_dist < adj_dist || (found_dist == adj_dist && found_step <= step) => {}
+                    _ => {
+                        reached[*adj] = Some((adj_dist, step));
+                        to_visit.push(Reverse((adj_dist, *adj)));
                     }
-                });
-                // println!("targets for {:?} = {:?}", adj, targets);
-                
-                if targets.len() > 0 {
-                    return (adj, Some(targets[0].0), Some(targets[0].1));
                 }
-                return (adj, None, None);
-            })
-            .filter(|(_adj, _targ, dist)| dist.is_some())
-            .map(|(adj, targ, dist)| (adj, targ.unwrap(), dist.unwrap()))
-            .collect();
+            }
+        }
+        reached
+    }
 
-        // println!("MOVES unsorted: {:?}", moves);
-        moves.sort_by(|a, b| {
-            // First on distance, then on reading_order of targets
-            if a.2 != b.2 {
-                a.2.partial_cmp(&b.2).unwrap()
-            } else {
-                reading_order(a.1, b.1)
+    fn print_map(&self) {
+        for (y, row) in self.tiles.chunks(self.width).enumerate() {
+            print!("{:02} ", y);
+            let mut hps: Vec<Vec<i32>> = vec![Vec::new(); self.config.factions.len()];
+            for (x, tile) in row.iter().enumerate() {
+                if let Some(id) = self.occupant[y * self.width + x] {
+                    let unit = &self.units[id];
+                    print!("{}", unit.team);
+                    let faction = self.config.factions.iter().position(|f| f.symbol == unit.team).unwrap();
+                    hps[faction].push(unit.hp);
+                } else {
+                    print!("{}", tile.symbol());
+                }
             }
-        });
-        // println!("MOVES sorted: {:?}", moves);
+            for (faction, hp) in self.config.factions.iter().zip(hps.iter()) {
+                if !hp.is_empty() {
+                    print!(" {} {:?}", faction.name, hp);
+                }
+            }
+            println!();
+        }
+    }
+}
 
+// The outcome with the puzzle factions, None if an elf died when they have to survive or in a
+// stalemate. The cave has to be one Battle::new accepts.
+fn simulate(input: &str, elf_power: i32, stop_on_elf_dead: bool) -> Option<i32> {
+    match battle(input, &BattleConfig::puzzle(elf_power), if stop_on_elf_dead { Some('E') } else { None }, &mut Vec::new()) {
+        Ok(Outcome::Won(outcome)) => Some(outcome),
+        Ok(_) => None,
+        Err(e) => panic!("bad cave: {}", e),
+    }
+}
 
-        // Move to the best position if there is one
-        if moves.len() > 0 {
-            // println!("Moving from {:?} to {:?}", good_guy.pos, moves);
-            good_guy.pos = moves[0].0.clone();
-            attacked = try_attack(&good_guy, all_units, elf_power);   // Attack directly after moving
+// Fights it out until no two units left are hostile, or until the ones left can't get at each
+// other. With a protected faction the battle is called off as soon as one of its units dies.
+// Everything that happens is added to events.
+fn battle(input: &str, config: &BattleConfig, protected: Option<char>, events: &mut Vec<Event>) -> Result<Outcome, String> {
+    let mut battle = Battle::new(input, config)?;
+    let protected_start = protected.map(|team| battle.alive_in(team));
+
+    while !battle.is_over() {
+        battle.step_round();
+        if protected.map(|team| battle.alive_in(team)) != protected_start {
+            println!("One of the {} died, don't continue", config.faction(protected.unwrap()).unwrap().name);
+            events.append(&mut battle.events);
+            return Ok(Outcome::Lost);
         }
     }
-    good_guy.acted = true;
-    all_units.insert(good_guy.pos.clone(), good_guy.clone());
-    return attacked;
+    if battle.stalemate {
+        println!("Stalemate after {} rounds, the units left can't get at each other: \n{:?}", battle.rounds, battle.alive().collect::<Vec<_>>());
+        events.append(&mut battle.events);
+        return Ok(Outcome::Stalemate);
+    }
+    let outcome = battle.outcome().unwrap();
+    let total_health = battle.alive().map(|unit| unit.hp).sum::<i32>();
+    println!("Game ended after {} rounds: \n{:?}", battle.rounds, battle.alive().collect::<Vec<_>>());
+    println!("Outcome: {} * {} = {}", battle.rounds, total_health, outcome);
+    events.append(&mut battle.events);
+    Ok(Outcome::Won(outcome))
 }
 
-fn simulate(input: &str, elf_power: i32, stop_on_elf_dead: bool) -> Option<i32> {
-    let mut walls = HashSet::new();
-    let mut all_units = HashMap::new();
-
-    for (y, line) in input.lines().enumerate() {
-        for (x, c) in line.chars().enumerate() {
-            if c == '#' {
-                walls.insert(Pos::new(x, y));
-            } else if c == 'G' || c == 'E' {
-                all_units.insert(Pos::new(x, y), Unit::new(x, y, c));
+// The battle after the given number of rounds of a log, or at the end if it was over before
+// that. The log doesn't say who is hostile, so the config only has the factions.
+fn replay(events: &[Event], rounds: i32) -> Result<Battle, String> {
+    let mut replayed: Option<Battle> = None;
+    for event in events {
+        if let Event::Cave { rows } = event {
+            replayed = Some(Battle::from_cave(rows, BattleConfig::empty())?);
+            continue;
+        }
+        let battle = replayed.as_mut().ok_or("the log doesn't start with the cave")?;
+        let occupant = |battle: &Battle, pos: &Pos| battle.cell(pos).and_then(|cell| battle.occupant[cell]).ok_or(format!("no unit at {:?}", pos));
+        match event {
+            Event::Cave { .. } => {}
+            Event::Faction { symbol, name, hp, attack } => {
+                battle.config.factions.push(Faction { symbol: *symbol, name: name.clone(), hp: *hp, attack: *attack });
+            }
+            Event::Unit { pos, team } => {
+                battle.config.faction(*team).ok_or(format!("unit of unknown faction {}", team))?;
+                battle.add_unit(pos, *team);
+            }
+            Event::RoundStart { round } => {
+                battle.rounds = *round;
+                if *round == rounds {
+                    break;
+                }
+            }
+            Event::Moved { from, to } => {
+                let id = occupant(battle, from)?;
+                let to = battle.cell(to).ok_or(format!("{:?} is outside the cave", to))?;
+                battle.move_unit(id, to);
+            }
+            Event::Attack { target: pos, hp, .. } | Event::Tile { pos, hp } => {
+                let id = occupant(battle, pos)?;
+                battle.units[id].hp = *hp;
+            }
+            Event::Died { pos, .. } => {
+                let id = occupant(battle, pos)?;
+                battle.occupant[battle.units[id].cell] = None;
+            }
+            Event::Ended { rounds, .. } => {
+                battle.rounds = *rounds;
+                battle.over = true;
+            }
+            Event::Stalemate { rounds } => {
+                battle.rounds = *rounds;
+                battle.over = true;
+                battle.stalemate = true;
             }
         }
     }
+    replayed.ok_or("the log is empty".to_string())
+}
 
-    let elf_count = all_units.iter().filter(|(_, unit)| unit.team == 'E').count();
+fn read_log(path: &str) -> Result<Vec<Event>, String> {
+    let log = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    log.lines().enumerate().map(|(i, line)| Event::parse(line).map_err(|e| format!("line {}: {}", i + 1, e))).collect()
+}
 
-    for turn in 0.. {
-        // println!("Turn: {}", turn);
-        // print_map(&walls, &all_units);
-        let mut todo_units: Vec<Pos> = all_units.iter().map(|(p, _)| p.clone()).collect();
-        all_units.values_mut().for_each(|unit| unit.acted = false);
-        todo_units.sort_by(reading_order);
-        for i in 0..todo_units.len() {
-            let unit_pos = &todo_units[i];
-            if let Some(unit) = all_units.get(&unit_pos) {
-                if unit.acted {
-                    continue;
+// Powers below the one the binary search finds that are tried as well, since more power doesn't
+// always mean fewer elf deaths, a stronger elf can kill its goblin sooner and get itself into a worse fight
+const VERIFY_BELOW: i32 = 3;
+
+// Finds the lowest elf power above 3 where no elf dies, by doubling until one is found and then
+// searching between the last failure and it. Returns the power, the outcome and how many
+// simulations it took, or None if the elves can't make it even when they kill with every hit.
+fn search_elf_power(input: &str) -> Option<(i32, i32, usize)> {
+    let mut outcomes: HashMap<i32, Option<i32>> = HashMap::new();
+    let mut outcome = |elf_power| *outcomes.entry(elf_power).or_insert_with(|| simulate(input, elf_power, true));
+
+    // 3 is what part 1 uses, counted as failing
+    let mut lo = 3;
+    let mut hi = 4;
+    while outcome(hi).is_none() {
+        if hi >= 200 {
+            return None;
+        }
+        lo = hi;
+        hi = (hi * 2).min(200);
+    }
+    while hi - lo > 1 {
+        let mid = (lo + hi) / 2;
+        if outcome(mid).is_some() {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    let mut elf_power = hi - 1;
+    while elf_power > 3 && elf_power >= hi - VERIFY_BELOW {
+        if outcome(elf_power).is_some() {
+            hi = elf_power;
+        }
+        elf_power -= 1;
+    }
+    let best = outcome(hi).unwrap();
+    Some((hi, best, outcomes.len()))
+}
+
+fn main() {
+    // `cargo run -- log cave out.jsonl [config]` writes the events of a battle, elves with power 3 against goblins
+    // unless there is a config, and `cargo run -- replay out.jsonl [round]` shows the map after every round or just one
+    let args: Vec<String> = env::args().collect();
+    if (args.len() == 4 || args.len() == 5) && args[1] == "log" {
+        let input = fs::read_to_string(&args[2]).expect("could not read cave");
+        let config = match args.get(4) {
+            Some(path) => BattleConfig::parse(&fs::read_to_string(path).expect("could not read config")).expect("bad config"),
+            None => BattleConfig::puzzle(3),
+        };
+        let mut events = Vec::new();
+        if let Err(e) = battle(&input, &config, None, &mut events) {
+            println!("{}: {}", args[2], e);
+            return;
+        }
+        let log: String = events.iter().map(|e| e.to_json() + "\n").collect();
+        fs::write(&args[3], log).expect("could not write log");
+        println!("Wrote {} events to {}", events.len(), args[3]);
+        return;
+    }
+    if (args.len() == 3 || args.len() == 4) && args[1] == "replay" {
+        let events = match read_log(&args[2]) {
+            Ok(events) => events,
+            Err(e) => { println!("{}: {}", args[2], e); return; }
+        };
+        let last = events.iter().filter_map(|e| match e { Event::RoundStart { round } => Some(*round), _ => None }).max().unwrap_or(0);
+        let rounds = match args.get(3) {
+            Some(round) => { let round = round.parse().expect("round should be a number"); round..=round }
+            None => 0..=last + 1,
+        };
+        for round in rounds {
+            match replay(&events, round) {
+                Ok(battle) => {
+                    println!("{} {} rounds", if battle.is_over() { "Over after" } else { "After" }, battle.rounds);
+                    battle.print_map();
                 }
+                Err(e) => { println!("{}: {}", args[2], e); return; }
             }
+        }
+        return;
+    }
 
-            if let Some(attacked) = perform_unit(unit_pos, &mut all_units, &walls, elf_power) {
-                if stop_on_elf_dead {
-                    let current_elfs_alive = all_units.iter().filter(|(_, unit)| unit.team == 'E').count();
-                    if elf_count != current_elfs_alive {
-                        println!("Elf died, don't contine with {}", elf_power);
-                        return None;
-                    }
+    // `cargo run -- battle cave config` fights a battle between the factions in the config file
+    if args.len() == 4 && args[1] == "battle" {
+        let input = fs::read_to_string(&args[2]).expect("could not read cave");
+        match BattleConfig::parse(&fs::read_to_string(&args[3]).expect("could not read config")) {
+            Ok(config) => {
+                if let Err(e) = battle(&input, &config, None, &mut Vec::new()) {
+                    println!("{}: {}", args[2], e);
                 }
+            }
+            Err(e) => println!("{}: {}", args[3], e),
+        }
+        return;
+    }
 
-                let team = all_units.values().next().unwrap().team;
-                if all_units.values().all(|unit| unit.team == team) {
-                    let completed_this = if i == todo_units.len() - 1 { 1 } else { 0 };
-                    println!("Game ended after {} turns: \n{:?}", turn, all_units);
-                    let turns = (turn + completed_this);
-                    let total_health = all_units.values().map(|unit| unit.hp).sum::<i32>();
-                    let answer = turns * total_health;
-                    println!("Outcome: {} * {} = {}", turns, total_health, answer);
-                    return Some(answer);
+    // `cargo run -- search cave` finds the lowest elf power where no elf dies for another cave
+    if args.len() == 3 && args[1] == "search" {
+        let input = fs::read_to_string(&args[2]).expect("could not read cave");
+        if let Err(e) = Battle::new(&input, &BattleConfig::puzzle(3)) {
+            println!("{}: {}", args[2], e);
+            return;
+        }
+        match search_elf_power(&input) {
+            Some((elf_power, outcome, simulations)) => println!("Elf power {} gives the outcome {} without elf deaths, found in {} simulations", elf_power, outcome, simulations),
+            None => println!("The elves lose someone whatever their power"),
+        }
+        return;
+    }
+
+    // `cargo run -- analyze cave out.csv [config]` fights the battle again without each unit, with every
+    // attack power from 3 to 40 and with other hp for each faction, and writes how every variant went
+    if (args.len() == 4 || args.len() == 5) && args[1] == "analyze" {
+        let input = fs::read_to_string(&args[2]).expect("could not read cave");
+        let config = match args.get(4) {
+            Some(path) => BattleConfig::parse(&fs::read_to_string(path).expect("could not read config")).expect("bad config"),
+            None => BattleConfig::puzzle(3),
+        };
+        if let Err(e) = Battle::new(&input, &config) {
+            println!("{}: {}", args[2], e);
+            return;
+        }
+        let changes = analysis::what_ifs(&input, &config, &(3..=40).collect::<Vec<_>>(), &[50, 100, 150, 250, 300]);
+        let variants = analysis::analyze(&input, &config, &changes);
+        analysis::print_table(&variants, &config);
+        for (i, faction) in config.factions.iter().enumerate() {
+            match analysis::best_removal(&variants, &config, faction.symbol) {
+                Some(best) if best.survivors[i] > variants[0].survivors[i] => {
+                    println!("{} saves the most {}: {} alive instead of {}", best.change, faction.name, best.survivors[i], variants[0].survivors[i]);
                 }
+                _ => println!("Leaving out a single enemy doesn't save any {}", faction.name),
             }
         }
+        fs::write(&args[3], analysis::to_csv(&variants, &config)).expect("could not write csv");
+        println!("Wrote {} variants to {}", variants.len(), args[3]);
+        return;
     }
-    return None;
-}
 
-fn main() {
-    // assert!(simulate(include_str!("test"), 3, false) == 27730);
-    // assert!(simulate(include_str!("test2"), 3, false) == 36334);
-    // assert!(simulate(include_str!("test3"), 3, false) == 39514);
-    // assert!(simulate(include_str!("test4"), 3, false) == 27755);
-    // assert!(simulate(include_str!("test5"), 3, false) == 28944);
-    // assert!(simulate(include_str!("test6"), 3, false) == 18740);
+    assert!(simulate(include_str!("test"), 3, false) == Some(27730));
+    assert!(simulate(include_str!("test2"), 3, false) == Some(36334));
+    assert!(simulate(include_str!("test3"), 3, false) == Some(39514));
+    assert!(simulate(include_str!("test4"), 3, false) == Some(27755));
+    assert!(simulate(include_str!("test5"), 3, false) == Some(28944));
+    assert!(simulate(include_str!("test6"), 3, false) == Some(18740));
+    // Part2 of the samples, with the lowest elf power that saves every elf
+    assert!(simulate(include_str!("test"), 15, true) == Some(4988) && simulate(include_str!("test"), 14, true).is_none());
+    assert!(simulate(include_str!("test4"), 15, true) == Some(3478) && simulate(include_str!("test4"), 14, true).is_none());
+    assert!(simulate(include_str!("test5"), 12, true) == Some(6474) && simulate(include_str!("test5"), 11, true).is_none());
+    assert!(simulate(include_str!("test6"), 34, true) == Some(1140) && simulate(include_str!("test6"), 33, true).is_none());
     assert!(simulate(include_str!("input"), 3, false).unwrap() == 225096);
 
-    for elf_power in 4.. {
-        if let Some(outcome) = simulate(include_str!("input"), elf_power, true) {
-            println!("Part2 Elf power {} lets us have the outcome {} without a single Elf death", elf_power, outcome);
-            assert!(outcome == 35354);
-            break;
-        }
-    }
+    // The same rules with other factions, three_way has an ogre that both elves and goblins fight
+    let puzzle = BattleConfig::parse("faction E Elfs 200 3\nfaction G Goblins 200 3\nhostile E G\n").unwrap();
+    assert!(battle(include_str!("test"), &puzzle, None, &mut Vec::new()) == Ok(Outcome::Won(27730)));
+    assert!(BattleConfig::parse("faction E Elfs 200 0\nfaction G Goblins 200 3\nhostile E G\n").is_err());
+    assert!(BattleConfig::parse("faction E Elfs 200 3\nfaction G Goblins -5 3\nhostile E G\n").err() == Some("line 2: faction G needs hp and attack power of at least 1".to_string()));
+    // The log of the first sample reads back the same and replays to the end state of the puzzle text
+    let mut events = Vec::new();
+    battle(include_str!("test"), &puzzle, None, &mut events).unwrap();
+    assert!(events.iter().all(|e| Event::parse(&e.to_json()).as_ref() == Ok(e)));
+    assert!(events.last() == Some(&Event::Ended { rounds: 47, hp: 590, outcome: 27730 }));
+    let end = replay(&events, 47).unwrap();
+    let mut end_hp: Vec<(usize, i32)> = end.alive().map(|unit| (unit.cell, unit.hp)).collect();
+    end_hp.sort();
+    let end_hp: Vec<(Pos, i32)> = end_hp.into_iter().map(|(cell, hp)| (end.pos(cell), hp)).collect();
+    assert!(end_hp == vec![(Pos::new(1, 1), 200), (Pos::new(2, 2), 131), (Pos::new(5, 3), 59), (Pos::new(5, 5), 200)]);
+    let three_way = BattleConfig::parse(include_str!("three_way.cfg")).unwrap();
+    assert!(battle(include_str!("three_way"), &three_way, None, &mut Vec::new()) == Ok(Outcome::Won(24957)));
+    // terrain has water around a trap and two healing springs
+    let terrain = BattleConfig::parse(include_str!("terrain.cfg")).unwrap();
+    let mut events = Vec::new();
+    assert!(battle(include_str!("terrain"), &terrain, None, &mut events) == Ok(Outcome::Won(12600)));
+    assert!(replay(&events, i32::MAX).unwrap().outcome() == Some(12600));
+    // A wall between the elf and the goblin ends in a stalemate after the first round where
+    // nobody moves, and the log replays to the same stalemate
+    let walled = "#######\n#E.#.G#\n#######\n";
+    let mut events = Vec::new();
+    assert!(battle(walled, &puzzle, None, &mut events) == Ok(Outcome::Stalemate));
+    assert!(events.last() == Some(&Event::Stalemate { rounds: 1 }));
+    assert!(events.iter().all(|e| Event::parse(&e.to_json()).as_ref() == Ok(e)));
+    let end = replay(&events, i32::MAX).unwrap();
+    assert!(end.is_over() && end.outcome().is_none());
+    // Symbols that are neither a tile nor a faction are rejected with where they are
+    assert!(battle("#####\n#E.G#\n#.X.#\n#####\n", &puzzle, None, &mut Vec::new()).err() == Some("unknown map symbol 'X' at 2,2".to_string()));
+    // Units and open tiles on the edge would step outside the cave, units count as floor there
+    assert!(battle("E.G\n", &puzzle, None, &mut Vec::new()).err() == Some("the cave needs a wall all around, '.' at 0,0".to_string()));
+    assert!(battle("####\n#E.G\n####\n", &puzzle, None, &mut Vec::new()).err() == Some("the cave needs a wall all around, '.' at 3,1".to_string()));
+    assert!(battle("#####\n#E.G#\n#####\n", &puzzle, None, &mut Vec::new()).is_ok());
+    assert!(BattleConfig::parse("trap -5\n").err() == Some("line 1: trap needs a value of at least 0".to_string()));
+    assert!(BattleConfig::parse("spring -1\n").is_err() && BattleConfig::parse("trap 0\nspring 0\n").is_ok());
+
+    // What-ifs on the samples: the sweep finds the same part 2 answer, and in the second sample
+    // one goblin left out keeps an elf alive
+    let puzzle = BattleConfig::puzzle(3);
+    let variants = analysis::analyze(include_str!("test"), &puzzle, &analysis::what_ifs(include_str!("test"), &puzzle, &[3, 14, 15], &[]));
+    assert!(variants[0].outcome == Some(27730) && variants[0].survivors == vec![0, 4]);
+    assert!(variants.iter().any(|v| v.change == analysis::Change::Attack { team: 'E', attack: 15 } && v.outcome == Some(4988) && v.survivors == vec![2, 0]));
+    let variants = analysis::analyze(include_str!("test2"), &puzzle, &analysis::what_ifs(include_str!("test2"), &puzzle, &[], &[]));
+    let best = analysis::best_removal(&variants, &puzzle, 'E').unwrap();
+    assert!(best.change == analysis::Change::Remove { pos: Pos::new(1, 1), team: 'G' } && best.survivors[0] == variants[0].survivors[0] + 1);
+    // Faction names can have commas and quotes, the csv keeps them in one field
+    let quoted = BattleConfig::parse("faction E El,\"fs\" 200 3\nfaction G Goblins 200 3\nhostile E G\n").unwrap();
+    let csv = analysis::to_csv(&analysis::analyze(include_str!("test"), &quoted, &[analysis::Change::Baseline]), &quoted);
+    assert!(csv == "\"change\",\"outcome\",\"rounds\",\"hp\",\"El,\"\"fs\"\"\",\"Goblins\"\n\"baseline\",27730,47,590,0,4\n");
+
+    assert!(search_elf_power(include_str!("test")).map(|(p, o, _)| (p, o)) == Some((15, 4988)));
+    assert!(search_elf_power(include_str!("test6")).map(|(p, o, _)| (p, o)) == Some((34, 1140)));
+
+    let (elf_power, outcome, simulations) = search_elf_power(include_str!("input")).unwrap();
+    println!("Part2 Elf power {} lets us have the outcome {} without a single Elf death, searched with {} simulations", elf_power, outcome, simulations);
+    assert!(elf_power == 40 && outcome == 35354);
 }
@@ -1,9 +1,12 @@
 extern crate regex;
 use regex::Regex;
-use std::cmp;
-use std::collections::{HashSet, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
 
-type Imm = i32;
+// Wide enough for every register width, results are fitted to the width by Arith
+type Imm = i128;
 type Reg = usize;
 
 #[derive(Debug)]
@@ -16,9 +19,9 @@ struct Sample {
 impl Sample {
     fn new(before: [Imm; 4], instr: [Imm; 4], after: [Imm; 4]) -> Sample {
         Sample {
-            before: before,
+            before,
             insn: instr,
-            after: after
+            after
         }
     }
 }
@@ -43,26 +46,153 @@ enum Insn {
 }
 
 
-fn execute(insn: Insn, regs: &mut [Imm; 4]) {
+const NUM_OPS: usize = 16;
+const NAMES: [&str; NUM_OPS] = [
+    "addr", "addi", "mulr", "muli", "banr", "bani", "borr", "bori",
+    "setr", "seti", "gtir", "gtri", "gtrr", "eqir", "eqri", "eqrr",
+];
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Insn::Addr(a, b, c) => write!(f, "addr {} {} {}", a, b, c),
+            Insn::Addi(a, b, c) => write!(f, "addi {} {} {}", a, b, c),
+            Insn::Mulr(a, b, c) => write!(f, "mulr {} {} {}", a, b, c),
+            Insn::Muli(a, b, c) => write!(f, "muli {} {} {}", a, b, c),
+            Insn::Banr(a, b, c) => write!(f, "banr {} {} {}", a, b, c),
+            Insn::Bani(a, b, c) => write!(f, "bani {} {} {}", a, b, c),
+            Insn::Borr(a, b, c) => write!(f, "borr {} {} {}", a, b, c),
+            Insn::Bori(a, b, c) => write!(f, "bori {} {} {}", a, b, c),
+            Insn::Setr(a, b, c) => write!(f, "setr {} {} {}", a, b, c),
+            Insn::Seti(a, b, c) => write!(f, "seti {} {} {}", a, b, c),
+            Insn::Gtir(a, b, c) => write!(f, "gtir {} {} {}", a, b, c),
+            Insn::Gtri(a, b, c) => write!(f, "gtri {} {} {}", a, b, c),
+            Insn::Gtrr(a, b, c) => write!(f, "gtrr {} {} {}", a, b, c),
+            Insn::Eqir(a, b, c) => write!(f, "eqir {} {} {}", a, b, c),
+            Insn::Eqri(a, b, c) => write!(f, "eqri {} {} {}", a, b, c),
+            Insn::Eqrr(a, b, c) => write!(f, "eqrr {} {} {}", a, b, c),
+        }
+    }
+}
+
+// Register width for deducing opcodes and running the test program, the device manual only says
+// "registers" so i32 is a guess that happens to fit both
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Width {
+    I32,
+    I64,
+    I128,
+}
+
+// What addr, addi, mulr and muli do when the result doesn't fit, the other opcodes can't overflow
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Overflow {
+    // The sample doesn't match that opcode, or the test program stops with an error
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Arith {
+    width: Width,
+    overflow: Overflow,
+}
+
+impl Default for Arith {
+    // i32 registers wrapping on overflow, what the samples were matched with before there was a choice
+    fn default() -> Arith {
+        Arith { width: Width::I32, overflow: Overflow::Wrapping }
+    }
+}
+
+impl Arith {
+    fn in_range(&self, v: Imm) -> bool {
+        match self.width {
+            Width::I32 => v >= i32::MIN as Imm && v <= i32::MAX as Imm,
+            Width::I64 => v >= i64::MIN as Imm && v <= i64::MAX as Imm,
+            Width::I128 => true,
+        }
+    }
+
+    // Truncates to the width like a two's complement register would, the B operand of addi, muli,
+    // bani and bori and the A operand of seti, gtir and eqir go through this too
+    fn wrap(&self, v: Imm) -> Imm {
+        match self.width {
+            Width::I32 => v as i32 as Imm,
+            Width::I64 => v as i64 as Imm,
+            Width::I128 => v,
+        }
+    }
+
+    // exact is None when the result doesn't even fit in i128, positive tells which way to saturate
+    fn fit(&self, exact: Option<Imm>, wrapped: Imm, positive: bool) -> Result<Imm, &'static str> {
+        match exact {
+            Some(v) if self.in_range(v) => Ok(v),
+            _ => match self.overflow {
+                Overflow::Checked => Err("overflow"),
+                Overflow::Wrapping => Ok(self.wrap(wrapped)),
+                Overflow::Saturating => Ok(match (self.width, positive) {
+                    (Width::I32, true) => i32::MAX as Imm,
+                    (Width::I32, false) => i32::MIN as Imm,
+                    (Width::I64, true) => i64::MAX as Imm,
+                    (Width::I64, false) => i64::MIN as Imm,
+                    (Width::I128, true) => Imm::MAX,
+                    (Width::I128, false) => Imm::MIN,
+                }),
+            },
+        }
+    }
+
+    fn add(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        let exact = a.checked_add(b);
+        self.fit(exact, a.wrapping_add(b), exact.map_or(a > 0, |v| v > 0))
+    }
+
+    fn mul(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        let exact = a.checked_mul(b);
+        self.fit(exact, a.wrapping_mul(b), exact.map_or((a > 0) == (b > 0), |v| v > 0))
+    }
+}
+
+// `--i32/--i64/--i128` picks the register width and `--checked/--wrapping/--saturating` what overflow does
+fn parse_arith(args: &[String]) -> Arith {
+    let mut arith = Arith::default();
+    for arg in args {
+        match arg.as_str() {
+            "--i32" => arith.width = Width::I32,
+            "--i64" => arith.width = Width::I64,
+            "--i128" => arith.width = Width::I128,
+            "--checked" => arith.overflow = Overflow::Checked,
+            "--wrapping" => arith.overflow = Overflow::Wrapping,
+            "--saturating" => arith.overflow = Overflow::Saturating,
+            _ => {}
+        }
+    }
+    arith
+}
+
+fn execute(insn: Insn, regs: &mut [Imm; 4], arith: Arith) -> Result<(), &'static str> {
+    let imm = |im: Imm| arith.wrap(im);
     match insn {
-        Insn::Addr(rn, rm, rd) => regs[rd] = regs[rn] + regs[rm],
-        Insn::Addi(rn, im, rd) => regs[rd] = regs[rn] + im,
-        Insn::Mulr(rn, rm, rd) => regs[rd] = regs[rn] * regs[rm],
-        Insn::Muli(rn, im, rd) => regs[rd] = regs[rn] * im,
+        Insn::Addr(rn, rm, rd) => regs[rd] = arith.add(regs[rn], regs[rm])?,
+        Insn::Addi(rn, im, rd) => regs[rd] = arith.add(regs[rn], imm(im))?,
+        Insn::Mulr(rn, rm, rd) => regs[rd] = arith.mul(regs[rn], regs[rm])?,
+        Insn::Muli(rn, im, rd) => regs[rd] = arith.mul(regs[rn], imm(im))?,
         Insn::Banr(rn, rm, rd) => regs[rd] = regs[rn] & regs[rm],
-        Insn::Bani(rn, im, rd) => regs[rd] = regs[rn] & im,
+        Insn::Bani(rn, im, rd) => regs[rd] = regs[rn] & imm(im),
         Insn::Borr(rn, rm, rd) => regs[rd] = regs[rn] | regs[rm],
-        Insn::Bori(rn, im, rd) => regs[rd] = regs[rn] | im,
+        Insn::Bori(rn, im, rd) => regs[rd] = regs[rn] | imm(im),
         Insn::Setr(rn, _, rd) => regs[rd] = regs[rn],
-        Insn::Seti(im, _, rd) => regs[rd] = im,
-        Insn::Gtir(im, rm, rd) => regs[rd] = if im > regs[rm] { 1 } else { 0 },
-        Insn::Gtri(rn, im, rd) => regs[rd] = if regs[rn] > im { 1 } else { 0 },
+        Insn::Seti(im, _, rd) => regs[rd] = imm(im),
+        Insn::Gtir(im, rm, rd) => regs[rd] = if imm(im) > regs[rm] { 1 } else { 0 },
+        Insn::Gtri(rn, im, rd) => regs[rd] = if regs[rn] > imm(im) { 1 } else { 0 },
         Insn::Gtrr(rn, rm, rd) => regs[rd] = if regs[rn] > regs[rm] { 1 } else { 0 },
-        Insn::Eqir(im, rm, rd) => regs[rd] = if im == regs[rm] { 1 } else { 0 },
-        Insn::Eqri(rn, im, rd) => regs[rd] = if regs[rn] == im { 1 } else { 0 },
+        Insn::Eqir(im, rm, rd) => regs[rd] = if imm(im) == regs[rm] { 1 } else { 0 },
+        Insn::Eqri(rn, im, rd) => regs[rd] = if regs[rn] == imm(im) { 1 } else { 0 },
         Insn::Eqrr(rn, rm, rd) => regs[rd] = if regs[rn] == regs[rm] { 1 } else { 0 },
-        _ => panic!("Unknown instruction")
     };
+    Ok(())
 }
 
 fn parse_insn(insn_order: Imm, insn: &[Imm; 4]) -> Insn {
@@ -87,103 +217,346 @@ fn parse_insn(insn_order: Imm, insn: &[Imm; 4]) -> Insn {
     }
 }
 
-fn part2(solves: &mut HashMap<Imm, HashSet<Imm>>, raw_insns: &Vec<[Imm; 4]>) {
-    let mut opcode_to_insn_order: HashMap<Imm, Imm> = HashMap::new();
-    let mut vec_solve: Vec<(Imm, Vec<Imm>)> = solves.iter()
-        .map(|(opcode, list_orders)| {
-            let mut sorted: Vec<Imm> = list_orders.iter().map(|v| *v).collect();
-            sorted.sort();
-            (*opcode, sorted)
-        }).collect();
-    vec_solve.sort_by(|a, b| {
-        a.0.partial_cmp(&b.0).unwrap()
-    });
-    for (opcode, solved_by) in &vec_solve {
-        println!("Opcode {} is solved by {:?}", opcode, solved_by);
-    }
+// What the samples tell about which instruction each opcode can be
+struct Deduction {
+    // Instructions (by their order in parse_insn) that match every sample of the opcode
+    candidates: Vec<Vec<Imm>>,
+    // ruled_out[opcode][order] lists the samples the instruction doesn't match
+    ruled_out: Vec<Vec<Vec<usize>>>,
+    // Samples that no instruction matches, or with an opcode that doesn't exist. They are left out of the deduction.
+    contradictory: Vec<usize>,
+    // Number of samples that match three or more instructions
+    three_or_more: usize,
+}
+
+// An instruction that overflows with checked arithmetic matches no sample
+fn matches(sample: &Sample, order: Imm, arith: Arith) -> bool {
+    let insn = parse_insn(order, &sample.insn);
+    let mut regs = sample.before;
+    execute(insn, &mut regs, arith).is_ok() && regs == sample.after
+}
 
-    while opcode_to_insn_order.len() < 16 {
-        let mut found_map_for = None;
-        for (opcode, solved_by) in solves.iter() {
-            if solved_by.len() == 1 {
-                let order = *solved_by.iter().next().unwrap();
-                found_map_for = Some(order);
-                opcode_to_insn_order.insert(*opcode, order);
-                break;
+fn valid_insn(insn: &[Imm; 4]) -> bool {
+    // Every instruction writes to a register, the operands are checked per instruction
+    (0..NUM_OPS as Imm).contains(&insn[0]) && (0..4).contains(&insn[3])
+}
+
+fn part1(samples: &[Sample], arith: Arith) -> Deduction {
+    let mut deduction = Deduction {
+        candidates: Vec::new(),
+        ruled_out: vec![vec![Vec::new(); NUM_OPS]; NUM_OPS],
+        contradictory: Vec::new(),
+        three_or_more: 0,
+    };
+    for (i, sample) in samples.iter().enumerate() {
+        let matching: Vec<Imm> = if valid_insn(&sample.insn) {
+            (0..NUM_OPS as Imm).filter(|order| fits_operands(*order, &sample.insn) && matches(sample, *order, arith)).collect()
+        } else {
+            Vec::new()
+        };
+        if matching.len() >= 3 {
+            deduction.three_or_more += 1;
+        }
+        if matching.is_empty() {
+            deduction.contradictory.push(i);
+            continue;
+        }
+        for order in 0..NUM_OPS as Imm {
+            if !matching.contains(&order) {
+                deduction.ruled_out[sample.insn[0] as usize][order as usize].push(i);
             }
         }
-        if let Some(order) = found_map_for {
-            for v in solves.values_mut() {
-                v.remove(&order);
+    }
+    deduction.candidates = deduction.ruled_out.iter()
+        .map(|by_order| (0..NUM_OPS as Imm).filter(|order| by_order[*order as usize].is_empty()).collect())
+        .collect();
+    deduction
+}
+
+// Register operands must name one of the 4 registers, or the sample can't be that instruction
+fn fits_operands(order: Imm, insn: &[Imm; 4]) -> bool {
+    let reg_a = !matches!(order, 9 | 10 | 13);
+    let reg_b = matches!(order, 0 | 2 | 4 | 6 | 10 | 12 | 13 | 15);
+    (!reg_a || (0..4).contains(&insn[1])) && (!reg_b || (0..4).contains(&insn[2]))
+}
+
+// Assigns the opcodes with a single candidate left and removes it from the others, until nothing changes.
+// Returns false if some opcode has no candidates left.
+fn propagate(candidates: &mut [Vec<Imm>]) -> bool {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for opcode in 0..candidates.len() {
+            match candidates[opcode].len() {
+                0 => return false,
+                1 => {
+                    let order = candidates[opcode][0];
+                    for (other, orders) in candidates.iter_mut().enumerate() {
+                        if other != opcode && orders.contains(&order) {
+                            orders.retain(|o| *o != order);
+                            changed = true;
+                        }
+                    }
+                }
+                _ => {}
             }
-        } else {
-            panic!("Could not solve, current state: {:?}", solves);
         }
     }
-    println!("Solution {:?}", opcode_to_insn_order);
+    true
+}
 
-    // Lets run all the instructions
-    let mut registers: [Imm; 4] = [0, 0, 0, 0];
-    for raw_insn in raw_insns {
-        let insn = parse_insn(*opcode_to_insn_order.get(&raw_insn[0]).unwrap(), raw_insn);
-        execute(insn, &mut registers);
+// Finds every assignment of opcodes to distinct instructions allowed by the candidates, up to limit of them
+fn solve(mut candidates: Vec<Vec<Imm>>, limit: usize, solutions: &mut Vec<Vec<Imm>>) {
+    if solutions.len() >= limit || !propagate(&mut candidates) {
+        return;
+    }
+    let undecided = (0..candidates.len())
+        .filter(|opcode| candidates[*opcode].len() > 1)
+        .min_by_key(|opcode| candidates[*opcode].len());
+    match undecided {
+        None => solutions.push(candidates.iter().map(|orders| orders[0]).collect()),
+        Some(opcode) => {
+            for order in candidates[opcode].clone() {
+                let mut guess = candidates.clone();
+                guess[opcode] = vec![order];
+                solve(guess, limit, solutions);
+            }
+        }
     }
-    println!("Part2: register 0 contains {}", registers[0]);
 }
 
-fn part1(samples: &Vec<Sample>) -> HashMap<Imm, HashSet<Imm>> {
-    let mut three_or_more_same_behaviour = 0;
-    let mut solves: HashMap<Imm, HashSet<Imm>> = HashMap::new();
-    for sample in samples {
-        let mut ok_count = 0;
-        for variant in 0..16 {
-            let mut regs = sample.before;
-            let insn = parse_insn(variant, &sample.insn);
-            execute(insn, &mut regs);
-            
-            let mut all_ok = true;
-            for i in 0..regs.len() {
-                if regs[i] != sample.after[i] {
-                    all_ok = false;
-                    break;
+fn explain(deduction: &Deduction, samples: &[Sample], arith: Arith) {
+    for &i in &deduction.contradictory {
+        let sample = &samples[i];
+        println!("Sample {} {:?} {:?} -> {:?} matches no instruction, ignoring it", i, sample.before, sample.insn, sample.after);
+    }
+    for (opcode, by_order) in deduction.ruled_out.iter().enumerate() {
+        let names: Vec<&str> = deduction.candidates[opcode].iter().map(|order| NAMES[*order as usize]).collect();
+        println!("Opcode {} can be {:?}", opcode, names);
+        for (order, ruled_out_by) in by_order.iter().enumerate() {
+            if let Some(&first) = ruled_out_by.first() {
+                let sample = &samples[first];
+                if !fits_operands(order as Imm, &sample.insn) {
+                    println!("    not {}, ruled out by {} samples, first is sample {} where {:?} has an operand that isn't a register",
+                        NAMES[order], ruled_out_by.len(), first, sample.insn);
+                    continue;
+                }
+                let mut regs = sample.before;
+                let insn = parse_insn(order as Imm, &sample.insn);
+                let text = format!("{}", insn);
+                match execute(insn, &mut regs, arith) {
+                    Ok(()) => println!("    not {}, ruled out by {} samples, first is sample {} where {} gives {:?} instead of {:?}",
+                        NAMES[order], ruled_out_by.len(), first, text, regs, sample.after),
+                    Err(e) => println!("    not {}, ruled out by {} samples, first is sample {} where {} stops with {}",
+                        NAMES[order], ruled_out_by.len(), first, text, e),
                 }
             }
-            if all_ok {
-                ok_count += 1;
-                if solves.contains_key(&sample.insn[0]) {
-                    solves.get_mut(&sample.insn[0]).unwrap().insert(variant);
-                } else {
-                    let mut compat = HashSet::new();
-                    compat.insert(variant);
-                    solves.insert(sample.insn[0], compat);
+        }
+    }
+}
+
+// Why an opcode can't be anything but kept: the samples that rule out the other instructions, and the
+// opcodes that had to be one of them, with the reasons for those in turn
+fn why_only(deduction: &Deduction, claimed_by: &[Option<usize>], opcode: usize, kept: Option<usize>, indent: usize) -> Vec<String> {
+    let pad = " ".repeat(indent);
+    // The first sample that rules out each instruction, grouped by sample
+    let mut by_sample: Vec<(usize, Vec<&str>)> = Vec::new();
+    for order in (0..NUM_OPS).filter(|order| Some(*order) != kept) {
+        if let Some(&first) = deduction.ruled_out[opcode][order].first() {
+            match by_sample.iter_mut().find(|(sample, _)| *sample == first) {
+                Some((_, names)) => names.push(NAMES[order]),
+                None => by_sample.push((first, vec![NAMES[order]])),
+            }
+        }
+    }
+    let mut lines: Vec<String> = by_sample.iter().map(|(sample, names)| format!("{}sample {} rules out {}", pad, sample, names.join(", "))).collect();
+    for order in (0..NUM_OPS).filter(|order| Some(*order) != kept && deduction.ruled_out[opcode][*order].is_empty()) {
+        if let Some(other) = claimed_by[order] {
+            lines.push(format!("{}{} is taken by opcode {}, which can't be anything else:", pad, NAMES[order], other));
+            lines.extend(why_only(deduction, claimed_by, other, Some(order), indent + 4));
+        }
+    }
+    lines
+}
+
+// Why no assignment fits the samples. Follows the propagation to the first opcode left without an
+// instruction, or finds the fewest opcodes that share fewer instructions than there are of them.
+fn explain_conflict(deduction: &Deduction) -> Vec<String> {
+    let mut candidates = deduction.candidates.clone();
+    // The opcode that had only the instruction left when it was taken out of the others
+    let mut claimed_by = vec![None; NUM_OPS];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for opcode in 0..NUM_OPS {
+            if candidates[opcode].is_empty() {
+                let mut lines = vec![format!("Opcode {} can't be any instruction:", opcode)];
+                lines.extend(why_only(deduction, &claimed_by, opcode, None, 4));
+                return lines;
+            }
+            if candidates[opcode].len() == 1 && claimed_by[candidates[opcode][0] as usize].is_none() {
+                let order = candidates[opcode][0];
+                claimed_by[order as usize] = Some(opcode);
+                for (other, orders) in candidates.iter_mut().enumerate() {
+                    if other != opcode {
+                        orders.retain(|o| *o != order);
+                    }
                 }
+                changed = true;
+            }
+        }
+    }
+    let shared = |set: u32| {
+        let mut orders: Vec<Imm> = (0..NUM_OPS).filter(|opcode| set >> opcode & 1 == 1).flat_map(|opcode| candidates[opcode].clone()).collect();
+        orders.sort();
+        orders.dedup();
+        orders
+    };
+    match (1..1u32 << NUM_OPS).filter(|set| shared(*set).len() < set.count_ones() as usize).min_by_key(|set| set.count_ones()) {
+        Some(set) => {
+            let opcodes: Vec<usize> = (0..NUM_OPS).filter(|opcode| set >> opcode & 1 == 1).collect();
+            let names: Vec<&str> = shared(set).iter().map(|order| NAMES[*order as usize]).collect();
+            vec![format!("Opcodes {:?} have to be different instructions, but the samples leave only {:?} between them", opcodes, names)]
+        }
+        None => Vec::new(),
+    }
+}
+
+// Finds the opcode table, the instruction order in parse_insn for every opcode, if the samples only allow one
+fn decode_table(deduction: &Deduction, samples: &[Sample], arith: Arith) -> Option<Vec<Imm>> {
+    explain(deduction, samples, arith);
+
+    let mut solutions = Vec::new();
+    solve(deduction.candidates.clone(), 100, &mut solutions);
+    match solutions.len() {
+        0 => {
+            println!("No opcode assignment fits all the samples");
+            for line in explain_conflict(deduction) {
+                println!("{}", line);
+            }
+            None
+        }
+        1 => {
+            println!("Solution {:?}", solutions[0]);
+            solutions.pop()
+        }
+        n => {
+            println!("{}{} opcode assignments fit the samples, can't tell which one is right:", if n >= 100 { "At least " } else { "" }, n);
+            for solution in &solutions {
+                let names: Vec<&str> = solution.iter().map(|order| NAMES[*order as usize]).collect();
+                println!("    {:?}", names);
+            }
+            None
+        }
+    }
+}
+
+// Runs every sample through the table and reports how many match per opcode, and every one that doesn't.
+// Returns the number of samples that don't match.
+fn verify(table: &[Imm], samples: &[Sample], arith: Arith) -> usize {
+    let mut matched = [0; NUM_OPS];
+    let mut total = [0; NUM_OPS];
+    let mut mismatches = 0;
+    for (i, sample) in samples.iter().enumerate() {
+        let opcode = sample.insn[0];
+        if !valid_insn(&sample.insn) || !fits_operands(table[opcode as usize], &sample.insn) {
+            println!("Sample {} {:?} is not a valid instruction", i, sample.insn);
+            mismatches += 1;
+            continue;
+        }
+        total[opcode as usize] += 1;
+        if matches(sample, table[opcode as usize], arith) {
+            matched[opcode as usize] += 1;
+        } else {
+            let mut regs = sample.before;
+            let insn = parse_insn(table[opcode as usize], &sample.insn);
+            let text = format!("{}", insn);
+            match execute(insn, &mut regs, arith) {
+                Ok(()) => println!("Sample {} {:?} {:?} -> {:?} does not match, {} gives {:?}",
+                    i, sample.before, sample.insn, sample.after, text, regs),
+                Err(e) => println!("Sample {} {:?} {:?} -> {:?} does not match, {} stops with {}",
+                    i, sample.before, sample.insn, sample.after, text, e),
             }
+            mismatches += 1;
         }
+    }
+    for opcode in 0..NUM_OPS {
+        println!("Opcode {} ({}): {}/{} samples match", opcode, NAMES[table[opcode] as usize], matched[opcode], total[opcode]);
+    }
+    println!("{} of {} samples match the table", samples.len() - mismatches, samples.len());
+    mismatches
+}
+
+// The table file has one "opcode name" line per opcode
+fn write_table(path: &str, table: &[Imm]) -> io::Result<()> {
+    let text: String = table.iter().enumerate()
+        .map(|(opcode, order)| format!("{} {}\n", opcode, NAMES[*order as usize]))
+        .collect();
+    fs::write(path, text)
+}
 
-        if ok_count >= 3 {
-            three_or_more_same_behaviour += 1;
+fn read_table(path: &str) -> Result<Vec<Imm>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    let mut table = vec![None; NUM_OPS];
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let opcode = match words.as_slice() {
+            [opcode, _] => opcode.parse::<usize>().ok().filter(|o| *o < NUM_OPS),
+            _ => None,
+        }.ok_or(format!("bad line in {}: {}", path, line))?;
+        let order = NAMES.iter().position(|name| *name == words[1]).ok_or(format!("unknown instruction in {}: {}", path, line))?;
+        if table.contains(&Some(order as Imm)) || table[opcode].is_some() {
+            return Err(format!("{} is listed twice in {}", line, path));
         }
+        table[opcode] = Some(order as Imm);
     }
+    table.into_iter().enumerate()
+        .map(|(opcode, order)| order.ok_or(format!("opcode {} is missing from {}", opcode, path)))
+        .collect()
+}
 
-    println!("Part1: num samples that show the same behaviour for three or more instructions: {}", three_or_more_same_behaviour);
-    return solves;
+// Every instruction needs an opcode in the table and registers that exist for its operands
+fn check_program(table: &[Imm], raw_insns: &[(usize, [Imm; 4])]) -> Result<(), String> {
+    for (line, raw_insn) in raw_insns {
+        if !(0..table.len() as Imm).contains(&raw_insn[0]) {
+            return Err(format!("line {}: {:?} has opcode {}, the table has opcodes 0 to {}", line, raw_insn, raw_insn[0], table.len() - 1));
+        }
+        if !valid_insn(raw_insn) || !fits_operands(table[raw_insn[0] as usize], raw_insn) {
+            return Err(format!("line {}: {:?} is {} with a register operand that isn't one of r0 to r3",
+                line, raw_insn, NAMES[table[raw_insn[0] as usize] as usize]));
+        }
+    }
+    Ok(())
 }
 
-fn main() {
-    let input = include_str!("input");
+// The program is checked before it runs. With checked arithmetic an overflow stops it with an error.
+fn run_program(table: &[Imm], raw_insns: &[(usize, [Imm; 4])], arith: Arith) -> Result<[Imm; 4], String> {
+    check_program(table, raw_insns)?;
+    let mut registers: [Imm; 4] = [0, 0, 0, 0];
+    for (line, raw_insn) in raw_insns {
+        let insn = parse_insn(table[raw_insn[0] as usize], raw_insn);
+        let text = format!("{}", insn);
+        let before = registers;
+        execute(insn, &mut registers, arith)
+            .map_err(|e| format!("line {}: {} stopped with {}, registers {:?}", line, text, e, before))?;
+    }
+    Ok(registers)
+}
 
+// Files without samples are taken as just a program, every instruction with the line it is on
+fn parse_input(input: &str) -> (Vec<Sample>, Vec<(usize, [Imm; 4])>) {
     let before_samp_r = Regex::new(r"Before: *\[(-?\d+), (-?\d+), (-?\d+), (-?\d+)\]").unwrap(); 
     let instr_r = Regex::new(r"(-?\d+) (-?\d+) (-?\d+) (-?\d+)").unwrap(); 
     let after_samp_r = Regex::new(r"After: *\[(-?\d+), (-?\d+), (-?\d+), (-?\d+)\]").unwrap();
 
-    let mut raw_insns: Vec<[Imm; 4]> = Vec::new();
+    let mut raw_insns: Vec<(usize, [Imm; 4])> = Vec::new();
     let mut samples: Vec<Sample> = Vec::new();
     let mut curr_before = None;
     let mut curr_instr = None;
     let mut blank_count = 0;
-    let mut parse_program = false;
-    for line in input.lines() {
-        if line.len() == 0 {
+    let mut parse_program = !input.contains("Before:");
+    for (line_nr, line) in input.lines().enumerate() {
+        if line.is_empty() {
             blank_count += 1;
             if blank_count >= 3 {
                 parse_program = true;
@@ -225,11 +598,88 @@ fn main() {
             let instr = [op, p0, p1, p2];
             curr_instr = Some(instr);
             if parse_program {
-                raw_insns.push(instr);
+                raw_insns.push((line_nr + 1, instr));
             }
         }
     }
 
-    let mut solves = part1(&samples);
-    part2(&mut solves, &raw_insns);
+    (samples, raw_insns)
+}
+
+fn main() {
+    // `cargo run -- export table` saves the deduced opcode table,
+    // `cargo run -- run table program` runs another program (or input file) with a saved table.
+    // The flags of parse_arith pick the arithmetic for any of them.
+    let args: Vec<String> = env::args().collect();
+    let arith = parse_arith(&args);
+    let args: Vec<String> = args.into_iter().filter(|a| !a.starts_with("--")).collect();
+    if args.len() == 4 && args[1] == "run" {
+        let table = match read_table(&args[2]) {
+            Ok(table) => table,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let (samples, raw_insns) = parse_input(&fs::read_to_string(&args[3]).expect("could not read program"));
+        if !samples.is_empty() {
+            verify(&table, &samples, arith);
+        }
+        match run_program(&table, &raw_insns, arith) {
+            Ok(registers) => println!("Registers after running {}: {:?}", args[3], registers),
+            Err(e) => println!("{}: {}", args[3], e),
+        }
+        return;
+    }
+
+    // The arithmetic modes, with opcodes in the order of parse_insn 2^30 * 4 doesn't fit in i32
+    let in_order: Vec<Imm> = (0..NUM_OPS as Imm).collect();
+    let big = [(1, [9, 1 << 30, 0, 0]), (2, [3, 0, 4, 0])];
+    let overflow_in = |width, overflow| run_program(&in_order, &big, Arith { width, overflow }).map(|regs| regs[0]);
+    assert!(overflow_in(Width::I32, Overflow::Checked).is_err());
+    assert!(overflow_in(Width::I32, Overflow::Wrapping) == Ok(0));
+    assert!(overflow_in(Width::I32, Overflow::Saturating) == Ok(i32::MAX as Imm));
+    assert!(overflow_in(Width::I64, Overflow::Checked) == Ok(1 << 32));
+    // A sample where addi wraps around only matches addi with i32 registers that wrap
+    let wraps = Sample::new([i32::MAX as Imm, 0, 0, 0], [7, 0, 1, 1], [i32::MAX as Imm, i32::MIN as Imm, 0, 0]);
+    assert!(matches(&wraps, 1, Arith::default()));
+    assert!(!matches(&wraps, 1, Arith { width: Width::I32, overflow: Overflow::Checked }));
+    assert!(!matches(&wraps, 1, Arith { width: Width::I64, overflow: Overflow::Wrapping }));
+    // Opcodes past the table and registers past r3 are errors with the line before anything runs
+    let (_, bad) = parse_input("9 1 0 0\n16 0 0 0\n");
+    assert!(run_program(&in_order, &bad, Arith::default()) == Err("line 2: [16, 0, 0, 0] has opcode 16, the table has opcodes 0 to 15".to_string()));
+    let (_, bad) = parse_input("9 1 0 0\n0 0 4 1\n");
+    assert!(run_program(&in_order, &bad, Arith::default()) == Err("line 2: [0, 0, 4, 1] is addr with a register operand that isn't one of r0 to r3".to_string()));
+    let (_, bad) = parse_input("9 1 0 0\n\n9 1 0 4\n");
+    assert!(run_program(&in_order, &bad, Arith::default()).unwrap_err().starts_with("line 3: [9, 1, 0, 4]"));
+
+    // Two samples that each only fit mulr but have different opcodes, and three opcodes that can only
+    // be addr or addi
+    let only_mulr = |opcode| Sample::new([3, 5, 0, 0], [opcode, 0, 1, 2], [3, 5, 15, 0]);
+    let conflict = explain_conflict(&part1(&[only_mulr(4), only_mulr(7)], arith));
+    assert!(conflict.len() == 4 && conflict[0] == "Opcode 7 can't be any instruction:" && conflict[2] == "    mulr is taken by opcode 4, which can't be anything else:");
+    assert!(conflict[1].starts_with("    sample 1 rules out addr, addi, muli") && conflict[3].starts_with("        sample 0 rules out addr"));
+    let add_one = |opcode| Sample::new([1, 1, 0, 0], [opcode, 0, 1, 0], [2, 1, 0, 0]);
+    let pigeonhole = explain_conflict(&part1(&[add_one(0), add_one(1), add_one(2)], arith));
+    assert!(pigeonhole == vec!["Opcodes [0, 1, 2] have to be different instructions, but the samples leave only [\"addr\", \"addi\"] between them"]);
+
+    let (samples, raw_insns) = parse_input(include_str!("input"));
+    let deduction = part1(&samples, arith);
+    println!("Part1: num samples that show the same behaviour for three or more instructions: {}", deduction.three_or_more);
+    let table = match decode_table(&deduction, &samples, arith) {
+        Some(table) => table,
+        None => return,
+    };
+    if verify(&table, &samples, arith) > 0 {
+        println!("The table doesn't match all samples, not running the program");
+        return;
+    }
+    if args.len() == 3 && args[1] == "export" {
+        write_table(&args[2], &table).expect("could not write table");
+        println!("Wrote opcode table to {}", args[2]);
+    }
+    match run_program(&table, &raw_insns, arith) {
+        Ok(registers) => println!("Part2: register 0 contains {}", registers[0]),
+        Err(e) => println!("{}", e),
+    }
 }
@@ -0,0 +1,183 @@
+// Assembler for elfcode with labels, comments and names, producing plain elfcode for parse_program()
+//
+//   #ip 4                  binds the ip register, `ip` is then an alias for it
+//   .equ target r5         names a register or a value
+//   loop:                  a label is the pc of the instruction that follows it
+//   addi r1 1 r1 ; ...     registers are written as r0..r5, ip or plain numbers
+//   jmp loop               becomes `seti loop-1 0 ip`, continuing at loop
+//   jr loop                becomes `addi ip offset ip`, continuing at loop
+//   seti 5 r3              ignored operands, like the middle one of seti/setr, can be left out
+//   out r2 / in r1         the extended instructions are accepted too
+use std::collections::HashMap;
+use super::{Imm, NUM_REG};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operand {
+    Reg,
+    Imm,
+    Ignored,
+}
+
+fn operand_kinds(op: &str) -> Option<[Operand; 3]> {
+    use self::Operand::*;
+    match op {
+        "addr" | "mulr" | "banr" | "borr" | "gtrr" | "eqrr" => Some([Reg, Reg, Reg]),
+        "addi" | "muli" | "bani" | "bori" | "gtri" | "eqri" => Some([Reg, Imm, Reg]),
+        "gtir" | "eqir" => Some([Imm, Reg, Reg]),
+        "setr" => Some([Reg, Ignored, Reg]),
+        "seti" => Some([Imm, Ignored, Reg]),
+        "divr" | "modr" | "shlr" | "shrr" => Some([Reg, Reg, Reg]),
+        "divi" | "modi" | "shli" | "shri" => Some([Reg, Imm, Reg]),
+        "out" => Some([Reg, Ignored, Ignored]),
+        "in" => Some([Ignored, Ignored, Reg]),
+        _ => None,
+    }
+}
+
+struct Line<'a> {
+    number: usize,
+    op: &'a str,
+    args: Vec<&'a str>,
+}
+
+struct Assembler<'a> {
+    ip_reg: Option<usize>,
+    equs: HashMap<&'a str, &'a str>,
+    labels: HashMap<&'a str, usize>,
+}
+
+impl<'a> Assembler<'a> {
+    fn lookup(&self, mut name: &'a str) -> &'a str {
+        // Constants may refer to other constants, the limit stops cycles
+        for _ in 0..self.equs.len() {
+            match self.equs.get(name) {
+                Some(value) => name = value,
+                None => break,
+            }
+        }
+        name
+    }
+
+    fn reg(&self, arg: &'a str) -> Result<Imm, String> {
+        let name = self.lookup(arg);
+        let reg = if name == "ip" {
+            self.ip_reg.ok_or("ip used without an #ip binding")?
+        } else {
+            let digits = name.strip_prefix('r').unwrap_or(name);
+            digits.parse::<usize>().map_err(|_| format!("expected a register, got '{}'", arg))?
+        };
+        if reg >= NUM_REG {
+            return Err(format!("there is no register {}", reg));
+        }
+        Ok(reg as Imm)
+    }
+
+    fn imm(&self, arg: &'a str) -> Result<Imm, String> {
+        let name = self.lookup(arg);
+        if let Some(pc) = self.labels.get(name) {
+            return Ok(*pc as Imm);
+        }
+        name.parse::<Imm>().map_err(|_| format!("expected a value or label, got '{}'", arg))
+    }
+
+    fn label(&self, arg: &'a str) -> Result<Imm, String> {
+        self.labels.get(self.lookup(arg)).map(|pc| *pc as Imm).ok_or(format!("unknown label '{}'", arg))
+    }
+
+    fn emit(&self, pc: usize, line: &Line<'a>) -> Result<String, String> {
+        match line.op {
+            "jmp" | "jr" => {
+                if line.args.len() != 1 {
+                    return Err(format!("{} takes a single label", line.op));
+                }
+                let ip = self.ip_reg.ok_or(format!("{} needs an #ip binding", line.op))?;
+                let target = self.label(line.args[0])?;
+                // The ip register is incremented after the jump, so aim for the pc before the target,
+                // which is -1 for a jump to the first instruction
+                if line.op == "jmp" {
+                    Ok(format!("seti {} 0 {}", target - 1, ip))
+                } else {
+                    Ok(format!("addi {} {} {}", ip, target - pc as Imm - 1, ip))
+                }
+            }
+            op => {
+                let kinds = operand_kinds(op).ok_or(format!("unknown instruction '{}'", op))?;
+                let mut args = line.args.clone();
+                let ignored = kinds.iter().filter(|k| **k == Operand::Ignored).count();
+                if args.len() + ignored == 3 {
+                    for (i, kind) in kinds.iter().enumerate() {
+                        if *kind == Operand::Ignored {
+                            args.insert(i, "0");
+                        }
+                    }
+                }
+                if args.len() != 3 {
+                    return Err(format!("{} takes 3 operands, got {}", op, args.len()));
+                }
+                let mut values = [0; 3];
+                for i in 0..3 {
+                    values[i] = match kinds[i] {
+                        Operand::Reg => self.reg(args[i])?,
+                        Operand::Imm | Operand::Ignored => self.imm(args[i])?,
+                    };
+                }
+                Ok(format!("{} {} {} {}", op, values[0], values[1], values[2]))
+            }
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<String, String> {
+    let mut asm = Assembler { ip_reg: None, equs: HashMap::new(), labels: HashMap::new() };
+    let mut lines: Vec<Line> = Vec::new();
+
+    // First pass collects the labels and constants, so they can be used before they are defined
+    for (i, text) in source.lines().enumerate() {
+        let number = i + 1;
+        let mut text = text.split(';').next().unwrap().trim();
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                return Err(format!("line {}: bad label '{}'", number, label));
+            }
+            if asm.labels.insert(label, lines.len()).is_some() {
+                return Err(format!("line {}: label '{}' defined twice", number, label));
+            }
+            text = text[colon + 1..].trim();
+        }
+
+        let mut words = text.split_whitespace();
+        let op = match words.next() {
+            Some(op) => op,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+        match op {
+            "#ip" => {
+                if args.len() != 1 {
+                    return Err(format!("line {}: #ip takes a single register", number));
+                }
+                let reg = asm.reg(args[0]).map_err(|e| format!("line {}: {}", number, e))?;
+                asm.ip_reg = Some(reg as usize);
+            }
+            ".equ" => {
+                if args.len() != 2 {
+                    return Err(format!("line {}: .equ takes a name and a value", number));
+                }
+                asm.equs.insert(args[0], args[1]);
+            }
+            _ => lines.push(Line { number, op, args }),
+        }
+    }
+
+    let mut out = String::new();
+    if let Some(ip) = asm.ip_reg {
+        out.push_str(&format!("#ip {}\n", ip));
+    }
+    for (pc, line) in lines.iter().enumerate() {
+        let insn = asm.emit(pc, line).map_err(|e| format!("line {}: {}", line.number, e))?;
+        out.push_str(&insn);
+        out.push('\n');
+    }
+    Ok(out)
+}
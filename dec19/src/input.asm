@@ -0,0 +1,48 @@
+; The input written out with names, r0 ends up as the sum of the divisors of target
+#ip 4
+.equ sum r0
+.equ a r1
+.equ b r2
+.equ tmp r3
+.equ target r5
+
+        jmp setup
+main:   seti 1 a                ; a = 1
+outer:  seti 1 b                ; b = 1
+inner:  mulr a b tmp
+        eqrr tmp target tmp
+        addr tmp ip ip          ; a * b == target skips the jump
+        jr next
+        addr a sum sum          ; sum += a
+next:   addi b 1 b
+        gtrr b target tmp
+        addr ip tmp ip          ; b > target leaves the inner loop
+        jmp inner
+        addi a 1 a
+        gtrr a target tmp
+        addr tmp ip ip          ; a > target leaves the outer loop
+        jmp outer
+        mulr ip ip ip           ; 16 * 16 is past the end, halt
+
+; target = 2 * 2 * 19 * 11 + 4 * 22 + 21
+setup:  addi target 2 target
+        mulr target target target
+        mulr ip target target
+        muli target 11 target
+        addi tmp 4 tmp
+        mulr tmp ip tmp
+        addi tmp 21 tmp
+        addr target tmp target
+        addr ip sum ip          ; part 2 starts with sum = 1 and makes target larger
+        jmp main
+
+; target += (27 * 28 + 29) * 30 * 14 * 32
+        setr ip tmp
+        mulr tmp ip tmp
+        addr ip tmp tmp
+        mulr ip tmp tmp
+        muli tmp 14 tmp
+        mulr tmp ip tmp
+        addr target tmp target
+        seti 0 sum
+        jmp main
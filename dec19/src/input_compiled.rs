@@ -0,0 +1,252 @@
+// Generated from elfcode by compile_program() in main.rs, do not edit
+#![allow(clippy::all)]
+
+pub fn run(r: &mut [i64; 6]) -> u64 {
+    let mut count = 0u64;
+    loop {
+        match r[4] {
+            0 => {
+                r[4] = i64::wrapping_add(0, 16);
+                count += 1;
+            }
+            1 => {
+                r[1] = 1;
+                r[2] = 1;
+                r[3] = i64::wrapping_mul(r[1], r[2]);
+                r[3] = (r[3] == r[5]) as i64;
+                r[4] = i64::wrapping_add(r[3], 5);
+                count += 5;
+            }
+            2 => {
+                r[2] = 1;
+                r[3] = i64::wrapping_mul(r[1], r[2]);
+                r[3] = (r[3] == r[5]) as i64;
+                r[4] = i64::wrapping_add(r[3], 5);
+                count += 4;
+            }
+            3 => {
+                r[3] = i64::wrapping_mul(r[1], r[2]);
+                r[3] = (r[3] == r[5]) as i64;
+                r[4] = i64::wrapping_add(r[3], 5);
+                count += 3;
+            }
+            4 => {
+                r[3] = (r[3] == r[5]) as i64;
+                r[4] = i64::wrapping_add(r[3], 5);
+                count += 2;
+            }
+            5 => {
+                r[4] = i64::wrapping_add(r[3], 5);
+                count += 1;
+            }
+            6 => {
+                r[4] = i64::wrapping_add(6, 1);
+                count += 1;
+            }
+            7 => {
+                r[0] = i64::wrapping_add(r[1], r[0]);
+                r[2] = i64::wrapping_add(r[2], 1);
+                r[3] = (r[2] > r[5]) as i64;
+                r[4] = i64::wrapping_add(10, r[3]);
+                count += 4;
+            }
+            8 => {
+                r[2] = i64::wrapping_add(r[2], 1);
+                r[3] = (r[2] > r[5]) as i64;
+                r[4] = i64::wrapping_add(10, r[3]);
+                count += 3;
+            }
+            9 => {
+                r[3] = (r[2] > r[5]) as i64;
+                r[4] = i64::wrapping_add(10, r[3]);
+                count += 2;
+            }
+            10 => {
+                r[4] = i64::wrapping_add(10, r[3]);
+                count += 1;
+            }
+            11 => {
+                r[4] = 2;
+                count += 1;
+            }
+            12 => {
+                r[1] = i64::wrapping_add(r[1], 1);
+                r[3] = (r[1] > r[5]) as i64;
+                r[4] = i64::wrapping_add(r[3], 14);
+                count += 3;
+            }
+            13 => {
+                r[3] = (r[1] > r[5]) as i64;
+                r[4] = i64::wrapping_add(r[3], 14);
+                count += 2;
+            }
+            14 => {
+                r[4] = i64::wrapping_add(r[3], 14);
+                count += 1;
+            }
+            15 => {
+                r[4] = 1;
+                count += 1;
+            }
+            16 => {
+                r[4] = i64::wrapping_mul(16, 16);
+                count += 1;
+            }
+            17 => {
+                r[5] = i64::wrapping_add(r[5], 2);
+                r[5] = i64::wrapping_mul(r[5], r[5]);
+                r[5] = i64::wrapping_mul(19, r[5]);
+                r[5] = i64::wrapping_mul(r[5], 11);
+                r[3] = i64::wrapping_add(r[3], 4);
+                r[3] = i64::wrapping_mul(r[3], 22);
+                r[3] = i64::wrapping_add(r[3], 21);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 9;
+            }
+            18 => {
+                r[5] = i64::wrapping_mul(r[5], r[5]);
+                r[5] = i64::wrapping_mul(19, r[5]);
+                r[5] = i64::wrapping_mul(r[5], 11);
+                r[3] = i64::wrapping_add(r[3], 4);
+                r[3] = i64::wrapping_mul(r[3], 22);
+                r[3] = i64::wrapping_add(r[3], 21);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 8;
+            }
+            19 => {
+                r[5] = i64::wrapping_mul(19, r[5]);
+                r[5] = i64::wrapping_mul(r[5], 11);
+                r[3] = i64::wrapping_add(r[3], 4);
+                r[3] = i64::wrapping_mul(r[3], 22);
+                r[3] = i64::wrapping_add(r[3], 21);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 7;
+            }
+            20 => {
+                r[5] = i64::wrapping_mul(r[5], 11);
+                r[3] = i64::wrapping_add(r[3], 4);
+                r[3] = i64::wrapping_mul(r[3], 22);
+                r[3] = i64::wrapping_add(r[3], 21);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 6;
+            }
+            21 => {
+                r[3] = i64::wrapping_add(r[3], 4);
+                r[3] = i64::wrapping_mul(r[3], 22);
+                r[3] = i64::wrapping_add(r[3], 21);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 5;
+            }
+            22 => {
+                r[3] = i64::wrapping_mul(r[3], 22);
+                r[3] = i64::wrapping_add(r[3], 21);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 4;
+            }
+            23 => {
+                r[3] = i64::wrapping_add(r[3], 21);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 3;
+            }
+            24 => {
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 2;
+            }
+            25 => {
+                r[4] = i64::wrapping_add(25, r[0]);
+                count += 1;
+            }
+            26 => {
+                r[4] = 0;
+                count += 1;
+            }
+            27 => {
+                r[3] = 27;
+                r[3] = i64::wrapping_mul(r[3], 28);
+                r[3] = i64::wrapping_add(29, r[3]);
+                r[3] = i64::wrapping_mul(30, r[3]);
+                r[3] = i64::wrapping_mul(r[3], 14);
+                r[3] = i64::wrapping_mul(r[3], 32);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[0] = 0;
+                r[4] = 0;
+                count += 9;
+            }
+            28 => {
+                r[3] = i64::wrapping_mul(r[3], 28);
+                r[3] = i64::wrapping_add(29, r[3]);
+                r[3] = i64::wrapping_mul(30, r[3]);
+                r[3] = i64::wrapping_mul(r[3], 14);
+                r[3] = i64::wrapping_mul(r[3], 32);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[0] = 0;
+                r[4] = 0;
+                count += 8;
+            }
+            29 => {
+                r[3] = i64::wrapping_add(29, r[3]);
+                r[3] = i64::wrapping_mul(30, r[3]);
+                r[3] = i64::wrapping_mul(r[3], 14);
+                r[3] = i64::wrapping_mul(r[3], 32);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[0] = 0;
+                r[4] = 0;
+                count += 7;
+            }
+            30 => {
+                r[3] = i64::wrapping_mul(30, r[3]);
+                r[3] = i64::wrapping_mul(r[3], 14);
+                r[3] = i64::wrapping_mul(r[3], 32);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[0] = 0;
+                r[4] = 0;
+                count += 6;
+            }
+            31 => {
+                r[3] = i64::wrapping_mul(r[3], 14);
+                r[3] = i64::wrapping_mul(r[3], 32);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[0] = 0;
+                r[4] = 0;
+                count += 5;
+            }
+            32 => {
+                r[3] = i64::wrapping_mul(r[3], 32);
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[0] = 0;
+                r[4] = 0;
+                count += 4;
+            }
+            33 => {
+                r[5] = i64::wrapping_add(r[5], r[3]);
+                r[0] = 0;
+                r[4] = 0;
+                count += 3;
+            }
+            34 => {
+                r[0] = 0;
+                r[4] = 0;
+                count += 2;
+            }
+            35 => {
+                r[4] = 0;
+                count += 1;
+            }
+            pc => panic!("pc {} is outside the program", pc),
+        }
+        if r[4] >= -1 && r[4] + 1 < 36 {
+            r[4] += 1;
+        } else {
+            break;
+        }
+    }
+    count
+}
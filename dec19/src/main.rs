@@ -1,7 +1,22 @@
 extern crate regex;
 use regex::Regex;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
 
-type Imm = i64;
+mod asm;
+mod input_compiled;
+mod shifts_compiled;
+mod symbolic;
+mod trace;
+
+use trace::{Trace, TraceWriter};
+
+// Registers are wide enough for every Width, values are kept in range by Arith
+type Imm = i128;
 type Reg = usize;
 const NUM_REG: usize = 6;
 
@@ -23,32 +38,207 @@ enum Insn {
     Eqir(Imm, Reg, Reg),
     Eqri(Reg, Imm, Reg),
     Eqrr(Reg, Reg, Reg),
+    // Extended instruction set, only accepted by parse_program when asked for. dec21 doesn't
+    // have these, it only reads the sixteen opcodes of the puzzle.
+    Divr(Reg, Reg, Reg),
+    Divi(Reg, Imm, Reg),
+    Modr(Reg, Reg, Reg),
+    Modi(Reg, Imm, Reg),
+    Shlr(Reg, Reg, Reg),
+    Shli(Reg, Imm, Reg),
+    Shrr(Reg, Reg, Reg),
+    Shri(Reg, Imm, Reg),
+    Out(Reg, Imm, Imm),
+    In(Imm, Imm, Reg),
+}
+
+// Values read by `in` and written by `out`
+#[derive(Default)]
+struct Io {
+    input: VecDeque<Imm>,
+    output: Vec<Imm>,
+}
+
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Insn::Addr(a, b, c) => write!(f, "addr {} {} {}", a, b, c),
+            Insn::Addi(a, b, c) => write!(f, "addi {} {} {}", a, b, c),
+            Insn::Mulr(a, b, c) => write!(f, "mulr {} {} {}", a, b, c),
+            Insn::Muli(a, b, c) => write!(f, "muli {} {} {}", a, b, c),
+            Insn::Banr(a, b, c) => write!(f, "banr {} {} {}", a, b, c),
+            Insn::Bani(a, b, c) => write!(f, "bani {} {} {}", a, b, c),
+            Insn::Borr(a, b, c) => write!(f, "borr {} {} {}", a, b, c),
+            Insn::Bori(a, b, c) => write!(f, "bori {} {} {}", a, b, c),
+            Insn::Setr(a, b, c) => write!(f, "setr {} {} {}", a, b, c),
+            Insn::Seti(a, b, c) => write!(f, "seti {} {} {}", a, b, c),
+            Insn::Gtir(a, b, c) => write!(f, "gtir {} {} {}", a, b, c),
+            Insn::Gtri(a, b, c) => write!(f, "gtri {} {} {}", a, b, c),
+            Insn::Gtrr(a, b, c) => write!(f, "gtrr {} {} {}", a, b, c),
+            Insn::Eqir(a, b, c) => write!(f, "eqir {} {} {}", a, b, c),
+            Insn::Eqri(a, b, c) => write!(f, "eqri {} {} {}", a, b, c),
+            Insn::Eqrr(a, b, c) => write!(f, "eqrr {} {} {}", a, b, c),
+            Insn::Divr(a, b, c) => write!(f, "divr {} {} {}", a, b, c),
+            Insn::Divi(a, b, c) => write!(f, "divi {} {} {}", a, b, c),
+            Insn::Modr(a, b, c) => write!(f, "modr {} {} {}", a, b, c),
+            Insn::Modi(a, b, c) => write!(f, "modi {} {} {}", a, b, c),
+            Insn::Shlr(a, b, c) => write!(f, "shlr {} {} {}", a, b, c),
+            Insn::Shli(a, b, c) => write!(f, "shli {} {} {}", a, b, c),
+            Insn::Shrr(a, b, c) => write!(f, "shrr {} {} {}", a, b, c),
+            Insn::Shri(a, b, c) => write!(f, "shri {} {} {}", a, b, c),
+            Insn::Out(a, b, c) => write!(f, "out {} {} {}", a, b, c),
+            Insn::In(a, b, c) => write!(f, "in {} {} {}", a, b, c),
+        }
+    }
 }
 
+// Register width, also for the ip register. U64 makes the extended comparisons and shr unsigned
+// since every value is kept between 0 and u64::MAX.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Width {
+    I64,
+    U64,
+    I128,
+}
+
+// What add, mul and div do when the result doesn't fit, shifts always drop the bits that fall out
+// and mod can't overflow
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Overflow {
+    // Stops the program with an error that has the pc and the registers, a trace being recorded
+    // ends at the instruction before
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Arith {
+    width: Width,
+    overflow: Overflow,
+}
+
+impl Default for Arith {
+    // i64 wrapping, which is also what the Rust from compile_program does
+    fn default() -> Arith {
+        Arith { width: Width::I64, overflow: Overflow::Wrapping }
+    }
+}
+
+impl Arith {
+    fn bits(&self) -> Imm {
+        if self.width == Width::I128 { 128 } else { 64 }
+    }
 
-fn execute(insn: &Insn, regs: &mut [Imm; NUM_REG]) {
+    fn in_range(&self, v: Imm) -> bool {
+        match self.width {
+            Width::I64 => v >= i64::MIN as Imm && v <= i64::MAX as Imm,
+            Width::U64 => v >= 0 && v <= u64::MAX as Imm,
+            Width::I128 => true,
+        }
+    }
+
+    // Truncates to the width, like a two's complement register would. Immediates and input
+    // go through this too, so -1 is all ones in u64.
+    fn wrap(&self, v: Imm) -> Imm {
+        match self.width {
+            Width::I64 => v as i64 as Imm,
+            Width::U64 => v as u64 as Imm,
+            Width::I128 => v,
+        }
+    }
+
+    // exact is None when the result doesn't even fit in i128, positive tells which way to saturate
+    fn fit(&self, exact: Option<Imm>, wrapped: Imm, positive: bool) -> Result<Imm, &'static str> {
+        match exact {
+            Some(v) if self.in_range(v) => Ok(v),
+            _ => match self.overflow {
+                Overflow::Checked => Err("overflow"),
+                Overflow::Wrapping => Ok(self.wrap(wrapped)),
+                Overflow::Saturating => Ok(match (self.width, positive) {
+                    (Width::I64, true) => i64::MAX as Imm,
+                    (Width::I64, false) => i64::MIN as Imm,
+                    (Width::U64, true) => u64::MAX as Imm,
+                    (Width::U64, false) => 0,
+                    (Width::I128, true) => Imm::MAX,
+                    (Width::I128, false) => Imm::MIN,
+                }),
+            },
+        }
+    }
+
+    fn add(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        let exact = a.checked_add(b);
+        self.fit(exact, a.wrapping_add(b), exact.map_or(a > 0, |v| v > 0))
+    }
+
+    fn mul(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        let exact = a.checked_mul(b);
+        self.fit(exact, a.wrapping_mul(b), exact.map_or((a > 0) == (b > 0), |v| v > 0))
+    }
+
+    fn div(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        if b == 0 {
+            return Err("division by zero");
+        }
+        let exact = a.checked_div(b);
+        self.fit(exact, a.wrapping_div(b), exact.is_none_or(|v| v > 0))
+    }
+
+    fn rem(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        if b == 0 {
+            return Err("division by zero");
+        }
+        Ok(a.checked_rem(b).unwrap_or(0))
+    }
+
+    // Shifts drop the bits that fall out, whatever the overflow mode. Shifting by a negative
+    // amount or by the width or more shifts everything out.
+    fn shift_left(&self, v: Imm, by: Imm) -> Imm {
+        if (0..self.bits()).contains(&by) { self.wrap(v << by) } else { 0 }
+    }
+
+    fn shift_right(&self, v: Imm, by: Imm) -> Imm {
+        if (0..self.bits()).contains(&by) { v >> by } else if v < 0 { -1 } else { 0 }
+    }
+}
+
+fn execute(insn: &Insn, regs: &mut [Imm; NUM_REG], io: &mut Io, arith: Arith) -> Result<(), &'static str> {
+    let imm = |im: &Imm| arith.wrap(*im);
     match insn {
-        Insn::Addr(rn, rm, rd) => regs[*rd] = regs[*rn] + regs[*rm],
-        Insn::Addi(rn, im, rd) => regs[*rd] = regs[*rn] + im,
-        Insn::Mulr(rn, rm, rd) => regs[*rd] = regs[*rn] * regs[*rm],
-        Insn::Muli(rn, im, rd) => regs[*rd] = regs[*rn] * im,
+        Insn::Addr(rn, rm, rd) => regs[*rd] = arith.add(regs[*rn], regs[*rm])?,
+        Insn::Addi(rn, im, rd) => regs[*rd] = arith.add(regs[*rn], imm(im))?,
+        Insn::Mulr(rn, rm, rd) => regs[*rd] = arith.mul(regs[*rn], regs[*rm])?,
+        Insn::Muli(rn, im, rd) => regs[*rd] = arith.mul(regs[*rn], imm(im))?,
         Insn::Banr(rn, rm, rd) => regs[*rd] = regs[*rn] & regs[*rm],
-        Insn::Bani(rn, im, rd) => regs[*rd] = regs[*rn] & im,
+        Insn::Bani(rn, im, rd) => regs[*rd] = regs[*rn] & imm(im),
         Insn::Borr(rn, rm, rd) => regs[*rd] = regs[*rn] | regs[*rm],
-        Insn::Bori(rn, im, rd) => regs[*rd] = regs[*rn] | im,
+        Insn::Bori(rn, im, rd) => regs[*rd] = regs[*rn] | imm(im),
         Insn::Setr(rn, _, rd) => regs[*rd] = regs[*rn],
-        Insn::Seti(im, _, rd) => regs[*rd] = *im,
-        Insn::Gtir(im, rm, rd) => regs[*rd] = if *im > regs[*rm] { 1 } else { 0 },
-        Insn::Gtri(rn, im, rd) => regs[*rd] = if regs[*rn] > *im { 1 } else { 0 },
+        Insn::Seti(im, _, rd) => regs[*rd] = imm(im),
+        Insn::Gtir(im, rm, rd) => regs[*rd] = if imm(im) > regs[*rm] { 1 } else { 0 },
+        Insn::Gtri(rn, im, rd) => regs[*rd] = if regs[*rn] > imm(im) { 1 } else { 0 },
         Insn::Gtrr(rn, rm, rd) => regs[*rd] = if regs[*rn] > regs[*rm] { 1 } else { 0 },
-        Insn::Eqir(im, rm, rd) => regs[*rd] = if *im == regs[*rm] { 1 } else { 0 },
-        Insn::Eqri(rn, im, rd) => regs[*rd] = if regs[*rn] == *im { 1 } else { 0 },
+        Insn::Eqir(im, rm, rd) => regs[*rd] = if imm(im) == regs[*rm] { 1 } else { 0 },
+        Insn::Eqri(rn, im, rd) => regs[*rd] = if regs[*rn] == imm(im) { 1 } else { 0 },
         Insn::Eqrr(rn, rm, rd) => regs[*rd] = if regs[*rn] == regs[*rm] { 1 } else { 0 },
-        _ => panic!("Unknown instruction")
+        Insn::Divr(rn, rm, rd) => regs[*rd] = arith.div(regs[*rn], regs[*rm])?,
+        Insn::Divi(rn, im, rd) => regs[*rd] = arith.div(regs[*rn], imm(im))?,
+        Insn::Modr(rn, rm, rd) => regs[*rd] = arith.rem(regs[*rn], regs[*rm])?,
+        Insn::Modi(rn, im, rd) => regs[*rd] = arith.rem(regs[*rn], imm(im))?,
+        Insn::Shlr(rn, rm, rd) => regs[*rd] = arith.shift_left(regs[*rn], regs[*rm]),
+        Insn::Shli(rn, im, rd) => regs[*rd] = arith.shift_left(regs[*rn], imm(im)),
+        Insn::Shrr(rn, rm, rd) => regs[*rd] = arith.shift_right(regs[*rn], regs[*rm]),
+        Insn::Shri(rn, im, rd) => regs[*rd] = arith.shift_right(regs[*rn], imm(im)),
+        Insn::Out(rn, _, _) => io.output.push(regs[*rn]),
+        // Reading past the end of the input gives -1
+        Insn::In(_, _, rd) => regs[*rd] = arith.wrap(io.input.pop_front().unwrap_or(-1)),
     };
+    Ok(())
 }
 
-fn parse_insn(insn_order: &str, insn: &[Imm; 3]) -> Insn {
+fn parse_insn(insn_order: &str, insn: &[Imm; 3], extended: bool) -> Insn {
     match insn_order {
         "addr" => Insn::Addr(insn[0] as Reg, insn[1] as Reg, insn[2] as Reg),
         "addi" => Insn::Addi(insn[0] as Reg, insn[1] as Imm, insn[2] as Reg),
@@ -66,33 +256,305 @@ fn parse_insn(insn_order: &str, insn: &[Imm; 3]) -> Insn {
         "eqir" => Insn::Eqir(insn[0] as Imm, insn[1] as Reg, insn[2] as Reg),
         "eqri" => Insn::Eqri(insn[0] as Reg, insn[1] as Imm, insn[2] as Reg),
         "eqrr" => Insn::Eqrr(insn[0] as Reg, insn[1] as Reg, insn[2] as Reg),
-        _ => panic!("not valid variant")
+        "divr" if extended => Insn::Divr(insn[0] as Reg, insn[1] as Reg, insn[2] as Reg),
+        "divi" if extended => Insn::Divi(insn[0] as Reg, insn[1] as Imm, insn[2] as Reg),
+        "modr" if extended => Insn::Modr(insn[0] as Reg, insn[1] as Reg, insn[2] as Reg),
+        "modi" if extended => Insn::Modi(insn[0] as Reg, insn[1] as Imm, insn[2] as Reg),
+        "shlr" if extended => Insn::Shlr(insn[0] as Reg, insn[1] as Reg, insn[2] as Reg),
+        "shli" if extended => Insn::Shli(insn[0] as Reg, insn[1] as Imm, insn[2] as Reg),
+        "shrr" if extended => Insn::Shrr(insn[0] as Reg, insn[1] as Reg, insn[2] as Reg),
+        "shri" if extended => Insn::Shri(insn[0] as Reg, insn[1] as Imm, insn[2] as Reg),
+        "out" if extended => Insn::Out(insn[0] as Reg, insn[1] as Imm, insn[2] as Imm),
+        "in" if extended => Insn::In(insn[0] as Imm, insn[1] as Imm, insn[2] as Reg),
+        _ => panic!("not valid variant: {}", insn_order)
+    }
+}
+
+// Loop shapes that can be replaced by a native computation, found by optimize(). dec21 has a copy
+// of the divide loop part, a fix to it belongs in both.
+#[derive(Debug)]
+enum Idiom {
+    // for a in outer..=target { for b in 1..=target { if a * b == target { sum += a } } }
+    DivisorSum { outer: Reg, inner: Reg, tmp: Reg, target: Reg, sum: Reg, exit: usize },
+    // quot = 0; while (quot + 1) * divisor <= dividend { quot += 1 }
+    DivideLoop { quot: Reg, tmp: Reg, dividend: Reg, divisor: Imm, exit: usize },
+}
+
+// Returns the register that isn't `known` if the pair contains it, for commutative operands
+fn other_operand(a: Reg, b: Reg, known: Reg) -> Option<Reg> {
+    if a == known {
+        Some(b)
+    } else if b == known {
+        Some(a)
+    } else {
+        None
+    }
+}
+
+fn all_distinct(regs: &[Reg]) -> bool {
+    regs.iter().enumerate().all(|(i, r)| !regs[i + 1..].contains(r))
+}
+
+fn match_divisor_sum(program: &[Insn], ip: Reg, start: usize) -> Option<Idiom> {
+    let code = program.get(start..start + 14)?;
+    let inner = match code[0] { Insn::Seti(1, _, b) => b, _ => return None };
+    let (outer, tmp) = match code[1] { Insn::Mulr(x, y, t) => (other_operand(x, y, inner)?, t), _ => return None };
+    let target = match code[2] { Insn::Eqrr(x, y, t) if t == tmp => other_operand(x, y, tmp)?, _ => return None };
+    match code[3] { Insn::Addr(x, y, d) if d == ip && other_operand(x, y, ip)? == tmp => {}, _ => return None };
+    match code[4] { Insn::Addi(x, 1, d) if x == ip && d == ip => {}, _ => return None };
+    let sum = match code[5] { Insn::Addr(x, y, d) if other_operand(x, y, outer)? == d => d, _ => return None };
+    match code[6] { Insn::Addi(x, 1, d) if x == inner && d == inner => {}, _ => return None };
+    match code[7] { Insn::Gtrr(x, y, t) if x == inner && y == target && t == tmp => {}, _ => return None };
+    match code[8] { Insn::Addr(x, y, d) if d == ip && other_operand(x, y, ip)? == tmp => {}, _ => return None };
+    match code[9] { Insn::Seti(v, _, d) if d == ip && v == start as Imm => {}, _ => return None };
+    match code[10] { Insn::Addi(x, 1, d) if x == outer && d == outer => {}, _ => return None };
+    match code[11] { Insn::Gtrr(x, y, t) if x == outer && y == target && t == tmp => {}, _ => return None };
+    match code[12] { Insn::Addr(x, y, d) if d == ip && other_operand(x, y, ip)? == tmp => {}, _ => return None };
+    match code[13] { Insn::Seti(v, _, d) if d == ip && v + 1 == start as Imm => {}, _ => return None };
+
+    if !all_distinct(&[ip, outer, inner, tmp, target, sum]) {
+        return None;
+    }
+    Some(Idiom::DivisorSum { outer, inner, tmp, target, sum, exit: start + 14 })
+}
+
+fn match_divide_loop(program: &[Insn], ip: Reg, start: usize) -> Option<Idiom> {
+    let code = program.get(start..start + 9)?;
+    let quot = match code[0] { Insn::Seti(0, _, c) => c, _ => return None };
+    let tmp = match code[1] { Insn::Addi(x, 1, d) if x == quot => d, _ => return None };
+    let divisor = match code[2] { Insn::Muli(x, k, d) if x == tmp && d == tmp && k > 0 => k, _ => return None };
+    let dividend = match code[3] { Insn::Gtrr(x, y, d) if x == tmp && d == tmp => y, _ => return None };
+    match code[4] { Insn::Addr(x, y, d) if d == ip && other_operand(x, y, ip)? == tmp => {}, _ => return None };
+    match code[5] { Insn::Addi(x, 1, d) if x == ip && d == ip => {}, _ => return None };
+    match code[6] { Insn::Seti(v, _, d) if d == ip && v == (start + 8) as Imm => {}, _ => return None };
+    match code[7] { Insn::Addi(x, 1, d) if x == quot && d == quot => {}, _ => return None };
+    match code[8] { Insn::Seti(v, _, d) if d == ip && v == start as Imm => {}, _ => return None };
+
+    if !all_distinct(&[ip, quot, tmp, dividend]) {
+        return None;
+    }
+    Some(Idiom::DivideLoop { quot, tmp, dividend, divisor, exit: start + 9 })
+}
+
+// Finds the known idioms in the program, indexed by the pc they start at
+fn optimize(program: &[Insn], ip_reg: Reg) -> Vec<Option<Idiom>> {
+    (0..program.len())
+        .map(|pc| match_divisor_sum(program, ip_reg, pc).or_else(|| match_divide_loop(program, ip_reg, pc)))
+        .collect()
+}
+
+// Performs the idiom on the registers and returns the pc to continue at, and how many instructions
+// the interpreter would have executed to get there. None if the registers are outside what the native version handles,
+// or if the loop would overflow somewhere, then the loop is interpreted instead.
+fn apply_idiom(idiom: &Idiom, regs: &mut [Imm; NUM_REG], arith: Arith) -> Option<(usize, u64)> {
+    match *idiom {
+        Idiom::DivisorSum { outer, inner, tmp, target, sum, exit } => {
+            let n = regs[target];
+            let a = regs[outer];
+            if n < 1 || a < 1 {
+                return None;
+            }
+            // The loop multiplies up to max(a, n) * n and counts a up to max(a, n) + 1
+            if !arith.in_range(a.max(n).checked_mul(n)?) || !arith.in_range(a.max(n) + 1) {
+                return None;
+            }
+            let mut divisors = 0;
+            let mut i = 1;
+            while i * i <= n {
+                if n % i == 0 {
+                    if i >= a {
+                        divisors += i;
+                    }
+                    if n / i != i && n / i >= a {
+                        divisors += n / i;
+                    }
+                }
+                i += 1;
+            }
+            // Every pass of the inner loop is 8 instructions and every pass of the outer loop
+            // adds 5 more, except that the last pass of each doesn't take the jump back
+            let outer_passes = (a.max(n) - a + 1) as u64;
+            let executed = outer_passes.saturating_mul((n as u64).saturating_mul(8).saturating_add(4)) - 1;
+            regs[sum] = arith.add(regs[sum], divisors).ok()?;
+            regs[outer] = a.max(n) + 1;
+            regs[inner] = n + 1;
+            regs[tmp] = 1;
+            Some((exit, executed))
+        }
+        Idiom::DivideLoop { quot, tmp, dividend, divisor, exit } => {
+            // The loop multiplies up to dividend + divisor
+            if arith.wrap(divisor) != divisor || !arith.in_range(regs[dividend] + divisor) {
+                return None;
+            }
+            regs[quot] = if regs[dividend] < 0 { 0 } else { regs[dividend] / divisor };
+            regs[tmp] = 1;
+            // 7 instructions per increment of quot, plus the setup and the exit
+            Some((exit, (regs[quot] as u64).saturating_mul(7).saturating_add(6)))
+        }
+    }
+}
+
+// Reads of the ip register can be replaced by the pc, since the ip register always holds it
+fn rust_reg(r: Reg, ip_reg: Reg, pc: usize) -> String {
+    if r == ip_reg {
+        format!("{}", pc)
+    } else {
+        format!("r[{}]", r)
+    }
+}
+
+fn insn_to_rust(insn: &Insn, ip_reg: Reg, pc: usize) -> String {
+    let reg = |r: &Reg| rust_reg(*r, ip_reg, pc);
+    let imm = |im: &Imm| *im as i64;
+    let (rd, expr) = match insn {
+        Insn::Addr(rn, rm, rd) => (rd, format!("i64::wrapping_add({}, {})", reg(rn), reg(rm))),
+        Insn::Addi(rn, im, rd) => (rd, format!("i64::wrapping_add({}, {})", reg(rn), imm(im))),
+        Insn::Mulr(rn, rm, rd) => (rd, format!("i64::wrapping_mul({}, {})", reg(rn), reg(rm))),
+        Insn::Muli(rn, im, rd) => (rd, format!("i64::wrapping_mul({}, {})", reg(rn), imm(im))),
+        Insn::Banr(rn, rm, rd) => (rd, format!("{} & {}", reg(rn), reg(rm))),
+        Insn::Bani(rn, im, rd) => (rd, format!("{} & {}", reg(rn), imm(im))),
+        Insn::Borr(rn, rm, rd) => (rd, format!("{} | {}", reg(rn), reg(rm))),
+        Insn::Bori(rn, im, rd) => (rd, format!("{} | {}", reg(rn), imm(im))),
+        Insn::Setr(rn, _, rd) => (rd, reg(rn)),
+        Insn::Seti(im, _, rd) => (rd, format!("{}", imm(im))),
+        Insn::Gtir(im, rm, rd) => (rd, format!("({} > {}) as i64", imm(im), reg(rm))),
+        Insn::Gtri(rn, im, rd) => (rd, format!("({} > {}) as i64", reg(rn), imm(im))),
+        Insn::Gtrr(rn, rm, rd) => (rd, format!("({} > {}) as i64", reg(rn), reg(rm))),
+        Insn::Eqir(im, rm, rd) => (rd, format!("({} == {}) as i64", imm(im), reg(rm))),
+        Insn::Eqri(rn, im, rd) => (rd, format!("({} == {}) as i64", reg(rn), imm(im))),
+        Insn::Eqrr(rn, rm, rd) => (rd, format!("({} == {}) as i64", reg(rn), reg(rm))),
+        Insn::Divr(rn, rm, rd) => (rd, format!("i64::wrapping_div({}, {})", reg(rn), reg(rm))),
+        Insn::Divi(rn, im, rd) => (rd, format!("i64::wrapping_div({}, {})", reg(rn), imm(im))),
+        Insn::Modr(rn, rm, rd) => (rd, format!("i64::wrapping_rem({}, {})", reg(rn), reg(rm))),
+        Insn::Modi(rn, im, rd) => (rd, format!("i64::wrapping_rem({}, {})", reg(rn), imm(im))),
+        Insn::Shlr(rn, rm, rd) => (rd, format!("shift_left({}, {})", reg(rn), reg(rm))),
+        Insn::Shli(rn, im, rd) => (rd, format!("shift_left({}, {})", reg(rn), imm(im))),
+        Insn::Shrr(rn, rm, rd) => (rd, format!("shift_right({}, {})", reg(rn), reg(rm))),
+        Insn::Shri(rn, im, rd) => (rd, format!("shift_right({}, {})", reg(rn), imm(im))),
+        Insn::Out(rn, _, _) => return format!("output.push({});", reg(rn)),
+        Insn::In(_, _, rd) => (rd, "input.pop_front().unwrap_or(-1)".to_string()),
+    };
+    format!("r[{}] = {};", rd, expr)
+}
+
+fn writes_reg(insn: &Insn, r: Reg) -> bool {
+    match insn {
+        Insn::Addr(_, _, rd) | Insn::Addi(_, _, rd) | Insn::Mulr(_, _, rd) | Insn::Muli(_, _, rd) |
+        Insn::Banr(_, _, rd) | Insn::Bani(_, _, rd) | Insn::Borr(_, _, rd) | Insn::Bori(_, _, rd) |
+        Insn::Setr(_, _, rd) | Insn::Seti(_, _, rd) | Insn::Gtir(_, _, rd) | Insn::Gtri(_, _, rd) |
+        Insn::Gtrr(_, _, rd) | Insn::Eqir(_, _, rd) | Insn::Eqri(_, _, rd) | Insn::Eqrr(_, _, rd) |
+        Insn::Divr(_, _, rd) | Insn::Divi(_, _, rd) | Insn::Modr(_, _, rd) | Insn::Modi(_, _, rd) |
+        Insn::Shlr(_, _, rd) | Insn::Shli(_, _, rd) | Insn::Shrr(_, _, rd) | Insn::Shri(_, _, rd) |
+        Insn::In(_, _, rd) => *rd == r,
+        Insn::Out(_, _, _) => false,
+    }
+}
+
+// Translates the program into a standalone rust function `run` that does the same as run_program,
+// returning the number of executed instructions. It uses i64 registers with the default Arith, so
+// arithmetic wraps and division by zero panics.
+// Programs using in/out get `input` and `output` parameters as well.
+// Any pc can be jumped to, so there is one match arm per pc. Each arm continues through the
+// instructions that follow it until one of them writes the ip register, so straight-line code
+// runs without going through the dispatch loop.
+fn compile_program(program: &[Insn], ip_reg: Reg) -> String {
+    let mut out = String::new();
+    out.push_str("// Generated from elfcode by compile_program() in main.rs, do not edit\n");
+    out.push_str("#![allow(clippy::all)]\n\n");
+    let uses_io = program.iter().any(|insn| matches!(insn, Insn::Out(_, _, _) | Insn::In(_, _, _)));
+    if uses_io {
+        out.push_str(&format!("pub fn run(r: &mut [i64; {}], input: &mut ::std::collections::VecDeque<i64>, output: &mut Vec<i64>) -> u64 {{\n", NUM_REG));
+    } else {
+        out.push_str(&format!("pub fn run(r: &mut [i64; {}]) -> u64 {{\n", NUM_REG));
+    }
+    out.push_str("    let mut count = 0u64;\n");
+    out.push_str("    loop {\n");
+    out.push_str(&format!("        match r[{}] {{\n", ip_reg));
+    for start in 0..program.len() {
+        out.push_str(&format!("            {} => {{\n", start));
+        let mut end = start;
+        loop {
+            out.push_str(&format!("                {}\n", insn_to_rust(&program[end], ip_reg, end)));
+            if writes_reg(&program[end], ip_reg) || end + 1 == program.len() {
+                break;
+            }
+            end += 1;
+        }
+        if !writes_reg(&program[end], ip_reg) {
+            out.push_str(&format!("                r[{}] = {};\n", ip_reg, end));
+        }
+        out.push_str(&format!("                count += {};\n", end - start + 1));
+        out.push_str("            }\n");
+    }
+    out.push_str("            pc => panic!(\"pc {} is outside the program\", pc),\n");
+    out.push_str("        }\n");
+    out.push_str(&format!("        if r[{0}] >= -1 && r[{0}] + 1 < {1} {{\n", ip_reg, program.len()));
+    out.push_str(&format!("            r[{}] += 1;\n", ip_reg));
+    out.push_str("        } else {\n");
+    out.push_str("            break;\n");
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    out.push_str("    count\n");
+    out.push_str("}\n");
+    if program.iter().any(|insn| matches!(insn, Insn::Shlr(_, _, _) | Insn::Shli(_, _, _) | Insn::Shrr(_, _, _) | Insn::Shri(_, _, _))) {
+        out.push_str("\nfn shift_left(v: i64, by: i64) -> i64 {\n");
+        out.push_str("    if (0..64).contains(&by) { v << by } else { 0 }\n");
+        out.push_str("}\n");
+        out.push_str("\nfn shift_right(v: i64, by: i64) -> i64 {\n");
+        out.push_str("    if (0..64).contains(&by) { v >> by } else if v < 0 { -1 } else { 0 }\n");
+        out.push_str("}\n");
     }
+    out
 }
 
-fn run_program(program: &Vec<Insn>, ip_reg: usize, regs: &mut [Imm; NUM_REG]) {
+// Returns the number of executed instructions, or what stopped the program if the arithmetic failed
+fn run_program(program: &[Insn], ip_reg: usize, regs: &mut [Imm; NUM_REG], io: &mut Io, arith: Arith, optimized: bool, mut trace: Option<&mut TraceWriter>) -> Result<u64, String> {
+    let idioms = if optimized { optimize(program, ip_reg) } else { Vec::new() };
+    for (pc, idiom) in idioms.iter().enumerate() {
+        if let Some(idiom) = idiom {
+            println!("Replacing loop at pc {} with {:?}", pc, idiom);
+        }
+    }
+
     let mut count = 0u64;
     loop {
         let pc = regs[ip_reg] as usize;
-        if count % 100_000_000 == 0 {
-            println!("executed {}... pc {}, {:?}, regs: {:?}", count, pc, program[pc], regs);
+        if count.is_multiple_of(100_000_000) {
+            println!("executed {}... pc {}, {}, regs: {:?}", count, pc, program[pc], regs);
+        }
+        if let Some((exit, executed)) = idioms.get(pc).and_then(|i| i.as_ref()).and_then(|i| apply_idiom(i, regs, arith)) {
+            regs[ip_reg] = exit as Imm - 1;
+            count += executed;
+        } else {
+            let before = *regs;
+            if let Err(e) = execute(&program[pc], regs, io, arith) {
+                return Err(format!("pc {}: {} stopped with {} after {} instructions, registers {:?}", pc, program[pc], e, count, before));
+            }
+            count += 1;
         }
-        execute(&program[pc], regs);
-        count += 1;
 
-        if regs[ip_reg] >= 0 && ((regs[ip_reg] + 1i64) as usize) < program.len() {
+        let halted = if regs[ip_reg] >= -1 && ((regs[ip_reg] + 1) as usize) < program.len() {
             regs[ip_reg] += 1;
+            false
         } else {
+            true
+        };
+        if let Some(ref mut trace) = trace {
+            if !trace.record(regs).expect("could not write trace") {
+                println!("Trace is full, stopping");
+                break;
+            }
+        }
+        if halted {
             break;
         }
     }
     println!("Executed {} instructions, resulting registers {:?}", count, regs);
+    Ok(count)
 }
 
-fn main() {
-    // let input = include_str!("test");
-    let input = include_str!("input");
-
+// The extended instructions are rejected unless extended is set
+fn parse_program(input: &str, extended: bool) -> (Vec<Insn>, Reg) {
     let ip_r = Regex::new(r"#ip (\d+)").unwrap(); 
     let instr_r = Regex::new(r"(.+) (-?\d+) (-?\d+) (-?\d+)").unwrap(); 
 
@@ -105,7 +567,7 @@ fn main() {
             let p1 = c.get(3).unwrap().as_str().parse::<Imm>().unwrap();
             let p2 = c.get(4).unwrap().as_str().parse::<Imm>().unwrap();
             let params = [p0, p1, p2];
-            let instr = parse_insn(op, &params);
+            let instr = parse_insn(op, &params, extended);
             program.push(instr);
         } else if let Some(c) = ip_r.captures(line) {
             ip_reg_id = Some(c.get(1).unwrap().as_str().parse::<usize>().unwrap());
@@ -113,20 +575,288 @@ fn main() {
             panic!("Unknown input: {}", line);
         }
     }
+    (program, ip_reg_id.expect("should have a ip reg"))
+}
+
+fn print_state(trace: &Trace, n: u64) {
+    match trace.state(n).expect("could not read trace") {
+        Some(regs) => {
+            let pc = regs[trace.ip_reg];
+            let insn = if pc >= 0 { trace.program.get(pc as usize).map(|s| s.as_str()) } else { None };
+            println!("state {}/{}: pc {}, {}, regs: {:?}", n, trace.len() - 1, pc, insn.unwrap_or("(outside program)"), regs);
+        }
+        None => println!("there is no state {}, the trace has {}", n, trace.len()),
+    }
+}
+
+// Steps through a recorded trace, reading commands from stdin:
+//   s [n]      step forward, b [n] step backwards, g <n> go to state n
+//   d <file>   show the first state where another trace differs
+fn replay(path: &str) {
+    let trace = Trace::open(path).expect("could not open trace");
+    println!("Opened {} with {} states", path, trace.len());
+    let mut current = 0u64;
+    print_state(&trace, current);
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.expect("could not read command");
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count = words.get(1).and_then(|w| w.parse::<u64>().ok());
+        match words.first() {
+            Some(&"s") => current = (current + count.unwrap_or(1)).min(trace.len() - 1),
+            Some(&"b") => current = current.saturating_sub(count.unwrap_or(1)),
+            Some(&"g") if count.is_some() => current = count.unwrap().min(trace.len() - 1),
+            Some(&"d") if words.len() == 2 => {
+                diff_traces(&trace, words[1]);
+                continue;
+            }
+            Some(&"q") => break,
+            _ => {
+                println!("commands: s [n], b [n], g <n>, d <file>, q");
+                continue;
+            }
+        }
+        print_state(&trace, current);
+    }
+}
+
+fn diff_traces(trace: &Trace, other_path: &str) {
+    let other = Trace::open(other_path).expect("could not open trace");
+    match trace::diff(trace, &other).expect("could not read trace") {
+        Some((n, a, b)) => println!("Traces differ at state {}: {:?} vs {:?}", n, a, b),
+        None => println!("Traces are identical, {} states", trace.len()),
+    }
+}
+
+fn print_paths(paths: &[symbolic::Path]) {
+    for path in paths {
+        let regs: Vec<String> = path.regs.iter().map(|r| r.to_string()).collect();
+        println!("{}: {} after {} instructions, registers [{}]", path.domain, path.end, path.executed, regs.join(", "));
+        for event in &path.events {
+            println!("    {}", event);
+        }
+    }
+}
+
+// The arithmetic mode from the --i64/--u64/--i128 and --checked/--wrapping/--saturating flags
+fn parse_arith(args: &[String]) -> Arith {
+    let mut arith = Arith::default();
+    for arg in args {
+        match arg.as_str() {
+            "--i64" => arith.width = Width::I64,
+            "--u64" => arith.width = Width::U64,
+            "--i128" => arith.width = Width::I128,
+            "--checked" => arith.overflow = Overflow::Checked,
+            "--wrapping" => arith.overflow = Overflow::Wrapping,
+            "--saturating" => arith.overflow = Overflow::Saturating,
+            _ => {}
+        }
+    }
+    arith
+}
+
+// Runs an elfcode file with the given r0 and records the trace
+fn record(args: &[String]) {
+    let optimized = args.iter().any(|a| a == "--optimized");
+    let extended = args.iter().any(|a| a == "--extended");
+    let arith = parse_arith(args);
+    let args: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    let (program, ip_reg) = parse_program(&fs::read_to_string(args[0]).expect("could not read program"), extended);
+    let mut regs: [Imm; NUM_REG] = [0; NUM_REG];
+    regs[0] = args[1].parse().expect("r0 should be a number");
+    let max_states = args.get(3).map(|m| m.parse().expect("max states should be a number")).unwrap_or(u64::MAX);
+    let mut trace = TraceWriter::create(args[2], &program, ip_reg, 4096, max_states, &regs).expect("could not create trace");
+    if let Err(e) = run_program(&program, ip_reg, &mut regs, &mut Io::default(), arith, optimized, Some(&mut trace)) {
+        println!("{}", e);
+    }
+    let states = trace.finish().expect("could not write trace");
+    println!("Recorded {} states in {}, {} bytes", states, args[2], fs::metadata(args[2]).unwrap().len());
+}
+
+// Runs an elfcode file using the extended instructions, with the given values as input
+fn run_extended(args: &[String]) {
+    let arith = parse_arith(args);
+    let args: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    let (program, ip_reg) = parse_program(&fs::read_to_string(args[0]).expect("could not read program"), true);
+    let mut io = Io {
+        input: args[1..].iter().map(|v| v.parse().expect("input should be numbers")).collect(),
+        output: Vec::new(),
+    };
+    let mut regs: [Imm; NUM_REG] = [0; NUM_REG];
+    let result = run_program(&program, ip_reg, &mut regs, &mut io, arith, false, None);
+    println!("Output: {:?}", io.output);
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+}
+
+fn main() {
+    // `cargo run -- run program [input...]` runs an elfcode file with the extended instructions enabled
+    // record and run take --i64/--u64/--i128 and --checked/--wrapping/--saturating, default is wrapping i64
+    // `cargo run -- record [--optimized] [--extended] program r0 out.trace [max states]` records the run of an elfcode file,
+    // `cargo run -- replay file.trace` steps through it and `cargo run -- diff a.trace b.trace` compares two.
+    // An --optimized trace has a single state for each replaced loop, so its states aren't instruction counts.
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 5 && args[1] == "record" {
+        record(&args[2..]);
+        return;
+    }
+    if args.len() >= 3 && args[1] == "run" {
+        run_extended(&args[2..]);
+        return;
+    }
+    if args.len() == 3 && args[1] == "replay" {
+        replay(&args[2]);
+        return;
+    }
+    if args.len() == 4 && args[1] == "diff" {
+        diff_traces(&Trace::open(&args[2]).expect("could not open trace"), &args[3]);
+        return;
+    }
+
+    // `cargo run -- compile > src/input_compiled.rs` regenerates the compiled version of the input,
+    // `cargo run -- compile program` compiles another elfcode file, with the extended instructions enabled
+    if args.len() >= 2 && args[1] == "compile" {
+        let source = match args.get(2) {
+            Some(path) => fs::read_to_string(path).expect("could not read program"),
+            None => include_str!("input").to_string(),
+        };
+        let (program, ip_reg) = parse_program(&source, args.len() > 2);
+        print!("{}", compile_program(&program, ip_reg));
+        return;
+    }
+
+    // `cargo run -- symbolic [program]` follows the input or another elfcode file with r0 unknown
+    if args.len() >= 2 && args[1] == "symbolic" {
+        let source = match args.get(2) {
+            Some(path) => fs::read_to_string(path).expect("could not read program"),
+            None => include_str!("input").to_string(),
+        };
+        let (program, ip_reg) = parse_program(&source, args.len() > 2);
+        print_paths(&symbolic::explore(&program, ip_reg, Arith::default(), 0, i64::MAX as Imm));
+        return;
+    }
+
+    // `cargo run -- asm file.asm` prints the elfcode for an assembly file
+    if args.len() == 3 && args[1] == "asm" {
+        let source = fs::read_to_string(&args[2]).expect("could not read assembly file");
+        match asm::assemble(&source) {
+            Ok(elfcode) => print!("{}", elfcode),
+            Err(e) => println!("{}: {}", args[2], e),
+        }
+        return;
+    }
+
+    // let input = include_str!("test");
+    let input = include_str!("input");
+    let (program, ip_reg) = parse_program(input, false);
 
     println!("Part1");
     let mut regs: [Imm; NUM_REG] = [0, 0, 0, 0, 0, 0];
-    run_program(&program, ip_reg_id.expect("should have a ip reg"), &mut regs);
+    let checked = Arith { width: Width::I64, overflow: Overflow::Checked };
+    let count = run_program(&program, ip_reg, &mut regs, &mut Io::default(), checked, false, None).expect("part 1 should not overflow");
+    let part1 = regs[0];
 
-    println!("Part2");  // Solved in pseudo file
-    let target_val = 10551345;
-    // let target_val = 945;
-    let mut sum = 0;
-    for i in 1..=target_val {
-        if target_val % i == 0 {
-            println!("Adding {}", i);
-            sum += i; // * (target_val/i); 
-        }
+    // Check that the compiled version of the input is up to date and behaves like the interpreter
+    assert!(compile_program(&program, ip_reg) == include_str!("input_compiled.rs"), "input_compiled.rs is out of date");
+    let mut compiled_regs: [i64; NUM_REG] = [0, 0, 0, 0, 0, 0];
+    let compiled_count = input_compiled::run(&mut compiled_regs);
+    println!("Compiled version executed {} instructions, resulting registers {:?}", compiled_count, compiled_regs);
+    assert!(compiled_regs.iter().map(|r| *r as Imm).eq(regs.iter().cloned()) && compiled_count == count);
+
+    // input.asm is a readable version of the input, check that it assembles to a program doing the same
+    let (assembled, assembled_ip_reg) = parse_program(&asm::assemble(include_str!("input.asm")).unwrap(), false);
+    let mut assembled_regs: [Imm; NUM_REG] = [0, 0, 0, 0, 0, 0];
+    assert!(run_program(&assembled, assembled_ip_reg, &mut assembled_regs, &mut Io::default(), Arith::default(), false, None) == Ok(count));
+    assert!(assembled_regs == regs);
+    // A jump back to the first instruction sets ip to -1, which the increment brings back to 0
+    let (counting, counting_ip_reg) = parse_program(&asm::assemble("#ip 4\nstart: addi r1 1 r1\ngtri r1 3 r2\naddr r2 ip ip\njmp start\n").unwrap(), false);
+    let mut counting_regs: [Imm; NUM_REG] = [0; NUM_REG];
+    run_program(&counting, counting_ip_reg, &mut counting_regs, &mut Io::default(), Arith::default(), false, None).unwrap();
+    assert!(counting_regs[1] == 4);
+
+    // A trace of the loop has one state per instruction and ends in the final registers. A trace
+    // file that is cut short or has a broken header or index can't be opened, and a snapshot that
+    // the index points at but isn't one is an error when it is read.
+    let trace_path = env::temp_dir().join("dec19-counting.trace");
+    let trace_path = trace_path.to_str().unwrap();
+    let mut counting_regs: [Imm; NUM_REG] = [0; NUM_REG];
+    let mut trace = TraceWriter::create(trace_path, &counting, counting_ip_reg, 4, u64::MAX, &counting_regs).unwrap();
+    let executed = run_program(&counting, counting_ip_reg, &mut counting_regs, &mut Io::default(), Arith::default(), false, Some(&mut trace)).unwrap();
+    trace.finish().unwrap();
+    let opened = Trace::open(trace_path).unwrap();
+    assert!(opened.len() == executed + 1 && opened.state(executed).unwrap() == Some(counting_regs));
+    assert!(opened.states().map(|regs| regs.unwrap()).nth(executed as usize) == Some(counting_regs) && opened.state(executed + 1).unwrap().is_none());
+    let recorded = fs::read(trace_path).unwrap();
+    // Cut off in the program text, without the index, no interval, a program longer than the file
+    // and a missing ip register
+    let header = |ip_reg: u8, interval: u8, text_len: u8| [b"ELFTRACE".to_vec(), vec![ip_reg, interval, text_len]].concat();
+    for broken in &[recorded[..12].to_vec(), recorded[..recorded.len() - 1].to_vec(), header(4, 0, 0), header(4, 4, 100), header(9, 4, 0)] {
+        fs::write(trace_path, broken).unwrap();
+        assert!(Trace::open(trace_path).err().map(|e| e.kind()) == Some(io::ErrorKind::InvalidData));
     }
-    println!("Register 0 contains {}", sum)
+    // The second snapshot, state 4, overwritten with a delta record
+    let index = recorded.len() - 24 - 8 * (executed as usize + 1).div_ceil(4);
+    let second = u64::from_le_bytes(recorded[index + 8..index + 16].try_into().unwrap()) as usize;
+    let mut broken = recorded.clone();
+    broken[second] = 0;
+    fs::write(trace_path, &broken).unwrap();
+    let opened = Trace::open(trace_path).unwrap();
+    assert!(opened.state(3).is_ok() && opened.state(5).err().map(|e| e.kind()) == Some(io::ErrorKind::InvalidData));
+    assert!(opened.states().nth(4).unwrap().is_err());
+    fs::remove_file(trace_path).unwrap();
+
+    // Check that the optimized run gives the same result as the plain one
+    let mut regs: [Imm; NUM_REG] = [0, 0, 0, 0, 0, 0];
+    run_program(&program, ip_reg, &mut regs, &mut Io::default(), Arith::default(), true, None).unwrap();
+    assert!(regs[0] == part1);
+
+    // The extended instructions, digits prints the digits of its input, their sum and the sum shifted back and forth
+    let (digits, digits_ip_reg) = parse_program(include_str!("digits"), true);
+    let mut io = Io::default();
+    io.input.push_back(1234);
+    let mut digits_regs: [Imm; NUM_REG] = [0; NUM_REG];
+    run_program(&digits, digits_ip_reg, &mut digits_regs, &mut io, Arith::default(), false, None).unwrap();
+    assert!(io.output == vec![4, 3, 2, 1, 10, 10]);
+    // Shifting by a negative amount or by 64 or more shifts everything out, compiled or not
+    let (shifts, shifts_ip_reg) = parse_program(include_str!("shifts"), true);
+    assert!(compile_program(&shifts, shifts_ip_reg) == include_str!("shifts_compiled.rs"), "shifts_compiled.rs is out of date");
+    let mut shifts_regs: [Imm; NUM_REG] = [0; NUM_REG];
+    let shifts_count = run_program(&shifts, shifts_ip_reg, &mut shifts_regs, &mut Io::default(), Arith::default(), false, None).unwrap();
+    let mut compiled_shifts_regs: [i64; NUM_REG] = [0; NUM_REG];
+    assert!(shifts_compiled::run(&mut compiled_shifts_regs) == shifts_count);
+    assert!(compiled_shifts_regs.iter().map(|r| *r as Imm).eq(shifts_regs.iter().cloned()) && shifts_regs[..5] == [12, 0, -1, 0, -1]);
+
+    // The arithmetic modes on the extended opcodes: -1 is all ones in u64, i64::MIN / -1 overflows
+    // like a multiplication does, and only i128 keeps the bit that i64::MIN << 1 pushes out
+    let (modes, modes_ip_reg) = parse_program("#ip 5\nseti -1 0 0\nseti -9223372036854775808 0 1\ndivi 1 -1 2\nshli 1 1 3\n", true);
+    let modes_in = |width, overflow| {
+        let mut regs: [Imm; NUM_REG] = [0; NUM_REG];
+        run_program(&modes, modes_ip_reg, &mut regs, &mut Io::default(), Arith { width, overflow }, false, None).map(|_| regs[..4].to_vec())
+    };
+    let min = i64::MIN as Imm;
+    assert!(modes_in(Width::I64, Overflow::Wrapping) == Ok(vec![-1, min, min, 0]));
+    assert!(modes_in(Width::I64, Overflow::Saturating) == Ok(vec![-1, min, i64::MAX as Imm, 0]));
+    assert!(modes_in(Width::I64, Overflow::Checked).is_err_and(|e| e.starts_with("pc 2: divi 1 -1 2")));
+    assert!(modes_in(Width::U64, Overflow::Checked) == Ok(vec![u64::MAX as Imm, -min, 0, 0]));
+    assert!(modes_in(Width::I128, Overflow::Checked) == Ok(vec![-1, min, -min, min * 2]));
+
+    println!("Part2");
+    let mut regs: [Imm; NUM_REG] = [1, 0, 0, 0, 0, 0];
+    run_program(&program, ip_reg, &mut regs, &mut Io::default(), Arith::default(), true, None).unwrap();
+    println!("Register 0 contains {}", regs[0]);
+
+    // Symbolic execution finds the number the setup block computes for each r0, and the summed divisors
+    println!("Symbolic");
+    let paths = symbolic::explore(&program, ip_reg, Arith::default(), 0, i64::MAX as Imm);
+    print_paths(&paths);
+    let path_for = |r0| paths.iter().find(|p| p.domain.contains(r0)).unwrap();
+    let (part1_path, part2_path) = (path_for(0), path_for(1));
+    assert!(part1_path.summaries[0].1[5] == symbolic::Value::Linear(0, 945));
+    // The summary shows the registers after the loop, with the ip register at its exit
+    let summary = "pc 2: r0 += sum of the divisors of r5 = 945 from r1 = 1 up, giving [1920, 946, 946, 1, 16, 945]";
+    assert!(part1_path.events.iter().any(|event| event == summary));
+    assert!(part1_path.regs[0].constant() == Some(part1) && part1_path.executed == count);
+    assert!(part2_path.summaries[0].1[5] == symbolic::Value::Linear(0, 10551345));
+    assert!(part2_path.regs[0].constant() == Some(regs[0]));
 }
@@ -0,0 +1,81 @@
+// Generated from elfcode by compile_program() in main.rs, do not edit
+#![allow(clippy::all)]
+
+pub fn run(r: &mut [i64; 6]) -> u64 {
+    let mut count = 0u64;
+    loop {
+        match r[5] {
+            0 => {
+                r[0] = 12;
+                r[1] = -12;
+                r[2] = -1;
+                r[3] = shift_right(r[0], r[2]);
+                r[4] = shift_right(r[1], r[2]);
+                r[2] = shift_right(r[1], 64);
+                r[1] = shift_left(r[0], 64);
+                r[5] = 6;
+                count += 7;
+            }
+            1 => {
+                r[1] = -12;
+                r[2] = -1;
+                r[3] = shift_right(r[0], r[2]);
+                r[4] = shift_right(r[1], r[2]);
+                r[2] = shift_right(r[1], 64);
+                r[1] = shift_left(r[0], 64);
+                r[5] = 6;
+                count += 6;
+            }
+            2 => {
+                r[2] = -1;
+                r[3] = shift_right(r[0], r[2]);
+                r[4] = shift_right(r[1], r[2]);
+                r[2] = shift_right(r[1], 64);
+                r[1] = shift_left(r[0], 64);
+                r[5] = 6;
+                count += 5;
+            }
+            3 => {
+                r[3] = shift_right(r[0], r[2]);
+                r[4] = shift_right(r[1], r[2]);
+                r[2] = shift_right(r[1], 64);
+                r[1] = shift_left(r[0], 64);
+                r[5] = 6;
+                count += 4;
+            }
+            4 => {
+                r[4] = shift_right(r[1], r[2]);
+                r[2] = shift_right(r[1], 64);
+                r[1] = shift_left(r[0], 64);
+                r[5] = 6;
+                count += 3;
+            }
+            5 => {
+                r[2] = shift_right(r[1], 64);
+                r[1] = shift_left(r[0], 64);
+                r[5] = 6;
+                count += 2;
+            }
+            6 => {
+                r[1] = shift_left(r[0], 64);
+                r[5] = 6;
+                count += 1;
+            }
+            pc => panic!("pc {} is outside the program", pc),
+        }
+        if r[5] >= -1 && r[5] + 1 < 7 {
+            r[5] += 1;
+        } else {
+            break;
+        }
+    }
+    count
+}
+
+fn shift_left(v: i64, by: i64) -> i64 {
+    if (0..64).contains(&by) { v << by } else { 0 }
+}
+
+fn shift_right(v: i64, by: i64) -> i64 {
+    if (0..64).contains(&by) { v >> by } else if v < 0 { -1 } else { 0 }
+}
@@ -0,0 +1,433 @@
+// Symbolic execution of elfcode with r0 left as an unknown
+//
+// Registers hold a*r0 + b where they can, anything else the executor can't follow becomes an
+// opaque expression. Whenever control flow depends on r0 the run is split into paths, each
+// with the set of r0 values that take it, and once a path pins r0 to one value everything on it
+// is concrete again. Loops the optimizer knows are summarized, a path ends when it halts or
+// comes back to a pc it went through already.
+//
+// Concrete values follow the Arith like the VM, values depending on r0 are assumed not to overflow.
+use std::fmt;
+use super::{apply_idiom, optimize, Arith, Idiom, Imm, Insn, NUM_REG};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    // a * r0 + b
+    Linear(Imm, Imm),
+    Opaque(String),
+}
+
+impl Value {
+    pub fn constant(&self) -> Option<Imm> {
+        match *self {
+            Value::Linear(0, b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Linear(0, b) => write!(f, "{}", b),
+            Value::Linear(a, b) => {
+                match a {
+                    1 => write!(f, "r0")?,
+                    -1 => write!(f, "-r0")?,
+                    _ => write!(f, "{}*r0", a)?,
+                }
+                match b {
+                    0 => Ok(()),
+                    _ if *b < 0 => write!(f, " - {}", -b),
+                    _ => write!(f, " + {}", b),
+                }
+            }
+            Value::Opaque(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+// The r0 values lo..=hi except the excluded ones, lo and hi are never excluded
+#[derive(Clone, Debug, PartialEq)]
+pub struct Domain {
+    pub lo: Imm,
+    pub hi: Imm,
+    pub excluded: Vec<Imm>,
+}
+
+impl Domain {
+    fn fixed(v: Imm) -> Domain {
+        Domain { lo: v, hi: v, excluded: Vec::new() }
+    }
+
+    pub fn contains(&self, v: Imm) -> bool {
+        self.lo <= v && v <= self.hi && !self.excluded.contains(&v)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lo > self.hi
+    }
+
+    fn single(&self) -> Option<Imm> {
+        if self.lo == self.hi { Some(self.lo) } else { None }
+    }
+
+    fn restrict(&self, lo: Imm, hi: Imm, excluded: Option<Imm>) -> Domain {
+        let mut domain = Domain { lo: self.lo.max(lo), hi: self.hi.min(hi), excluded: self.excluded.clone() };
+        domain.excluded.extend(excluded);
+        while domain.lo <= domain.hi && domain.excluded.contains(&domain.lo) {
+            domain.lo += 1;
+        }
+        while domain.lo <= domain.hi && domain.excluded.contains(&domain.hi) {
+            domain.hi -= 1;
+        }
+        let (lo, hi) = (domain.lo, domain.hi);
+        domain.excluded.retain(|v| lo < *v && *v < hi);
+        domain.excluded.sort();
+        domain
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.single() {
+            Some(v) => write!(f, "r0 = {}", v)?,
+            None => write!(f, "{} <= r0 <= {}", self.lo, self.hi)?,
+        }
+        if !self.excluded.is_empty() {
+            let excluded: Vec<String> = self.excluded.iter().map(|v| v.to_string()).collect();
+            write!(f, " except {}", excluded.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Whether a condition on r0 holds in a domain, or the domains to split it into so it is known in each
+enum Decision {
+    Known(bool),
+    Split(Vec<Domain>),
+}
+
+// d == 0 for d = a*r0 + b
+fn decide_zero(a: Imm, b: Imm, domain: &Domain) -> Decision {
+    if a == 0 {
+        return Decision::Known(b == 0);
+    }
+    if b % a != 0 || !domain.contains(-b / a) {
+        return Decision::Known(false);
+    }
+    let root = -b / a;
+    if domain.single().is_some() {
+        return Decision::Known(true);
+    }
+    Decision::Split(vec![Domain::fixed(root), domain.restrict(domain.lo, domain.hi, Some(root))])
+}
+
+// d > 0 for d = a*r0 + b
+fn decide_positive(a: Imm, b: Imm, domain: &Domain) -> Decision {
+    if a == 0 {
+        return Decision::Known(b > 0);
+    }
+    let (holds, fails) = if a > 0 {
+        let least = (-b).div_euclid(a) + 1;
+        (domain.restrict(least, Imm::MAX, None), domain.restrict(Imm::MIN, least - 1, None))
+    } else {
+        let most = (b - 1).div_euclid(-a);
+        (domain.restrict(Imm::MIN, most, None), domain.restrict(most + 1, Imm::MAX, None))
+    };
+    match (holds.is_empty(), fails.is_empty()) {
+        (true, _) => Decision::Known(false),
+        (_, true) => Decision::Known(true),
+        _ => Decision::Split(vec![holds, fails]),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum End {
+    // The ip left the program, with the value of the ip register
+    Halted(Value),
+    // Came back to a pc, without a summary for the loop
+    Loop(usize),
+    // Control flow depends on something the executor can't follow
+    Unknown(usize, String),
+    // The arithmetic failed, like division by zero
+    Stopped(usize, &'static str),
+}
+
+impl fmt::Display for End {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            End::Halted(ip) => write!(f, "halts with ip {}", ip),
+            End::Loop(pc) => write!(f, "loops back to pc {}", pc),
+            End::Unknown(pc, why) => write!(f, "can't follow pc {}, {}", pc, why),
+            End::Stopped(pc, e) => write!(f, "stops with {} at pc {}", e, pc),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    domain: Domain,
+    regs: Vec<Value>,
+    pc: usize,
+    executed: u64,
+    visited: Vec<bool>,
+    events: Vec<String>,
+    summaries: Vec<(usize, Vec<Value>)>,
+}
+
+pub struct Path {
+    pub domain: Domain,
+    pub regs: Vec<Value>,
+    pub executed: u64,
+    // What happened on the way, in order
+    pub events: Vec<String>,
+    // The pc of every summarized loop with the registers going into it
+    pub summaries: Vec<(usize, Vec<Value>)>,
+    pub end: End,
+}
+
+impl State {
+    // Restricts the state to part of its domain, making everything concrete if r0 is pinned down
+    fn split(&self, domain: Domain) -> State {
+        let mut state = self.clone();
+        if let Some(v) = domain.single() {
+            for reg in state.regs.iter_mut() {
+                if let Value::Linear(a, b) = *reg {
+                    *reg = match a.checked_mul(v).and_then(|av| av.checked_add(b)) {
+                        Some(c) => Value::Linear(0, c),
+                        None => Value::Opaque(format!("({})", reg)),
+                    };
+                }
+            }
+        }
+        state.domain = domain;
+        state
+    }
+}
+
+fn add(x: &Value, y: &Value, arith: Arith) -> Result<Value, &'static str> {
+    match (x, y) {
+        (Value::Linear(0, b), Value::Linear(0, d)) => Ok(Value::Linear(0, arith.add(*b, *d)?)),
+        (Value::Linear(a, b), Value::Linear(c, d)) => Ok(match (a.checked_add(*c), b.checked_add(*d)) {
+            (Some(ac), Some(bd)) => Value::Linear(ac, bd),
+            _ => Value::Opaque(format!("({} + {})", x, y)),
+        }),
+        _ => Ok(Value::Opaque(format!("({} + {})", x, y))),
+    }
+}
+
+fn mul(x: &Value, y: &Value, arith: Arith) -> Result<Value, &'static str> {
+    match (x, y) {
+        (Value::Linear(0, b), Value::Linear(0, d)) => Ok(Value::Linear(0, arith.mul(*b, *d)?)),
+        (Value::Linear(a, b), Value::Linear(0, k)) | (Value::Linear(0, k), Value::Linear(a, b)) => {
+            Ok(match (a.checked_mul(*k), b.checked_mul(*k)) {
+                (Some(ak), Some(bk)) => Value::Linear(ak, bk),
+                _ => Value::Opaque(format!("({} * {})", x, y)),
+            })
+        }
+        _ => Ok(Value::Opaque(format!("({} * {})", x, y))),
+    }
+}
+
+// The operations that are only worked out for concrete values
+fn binary<F>(x: &Value, y: &Value, op: &str, f: F) -> Result<Value, &'static str>
+where
+    F: Fn(Imm, Imm) -> Result<Imm, &'static str>,
+{
+    match (x.constant(), y.constant()) {
+        (Some(a), Some(b)) => Ok(Value::Linear(0, f(a, b)?)),
+        _ => Ok(Value::Opaque(format!("({} {} {})", x, op, y))),
+    }
+}
+
+// x - y, as a linear function of r0 if it is one
+fn difference(x: &Value, y: &Value) -> Option<(Imm, Imm)> {
+    match (x, y) {
+        (Value::Linear(a, b), Value::Linear(c, d)) => Some((a.checked_sub(*c)?, b.checked_sub(*d)?)),
+        _ => None,
+    }
+}
+
+enum Outcome {
+    Value(Value),
+    Split(Vec<Domain>),
+}
+
+fn compare(x: &Value, y: &Value, equal: bool, domain: &Domain) -> Outcome {
+    let decision = match difference(x, y) {
+        Some((a, b)) if equal => decide_zero(a, b, domain),
+        Some((a, b)) => decide_positive(a, b, domain),
+        None => return Outcome::Value(Value::Opaque(format!("({} {} {})", x, if equal { "==" } else { ">" }, y))),
+    };
+    match decision {
+        Decision::Known(holds) => Outcome::Value(Value::Linear(0, holds as Imm)),
+        Decision::Split(domains) => Outcome::Split(domains),
+    }
+}
+
+fn execute(insn: &Insn, state: &mut State, arith: Arith) -> Result<Outcome, &'static str> {
+    let regs = state.regs.clone();
+    let imm = |im: &Imm| Value::Linear(0, arith.wrap(*im));
+    let domain = &state.domain;
+    let (rd, outcome) = match insn {
+        Insn::Addr(rn, rm, rd) => (rd, Outcome::Value(add(&regs[*rn], &regs[*rm], arith)?)),
+        Insn::Addi(rn, im, rd) => (rd, Outcome::Value(add(&regs[*rn], &imm(im), arith)?)),
+        Insn::Mulr(rn, rm, rd) => (rd, Outcome::Value(mul(&regs[*rn], &regs[*rm], arith)?)),
+        Insn::Muli(rn, im, rd) => (rd, Outcome::Value(mul(&regs[*rn], &imm(im), arith)?)),
+        Insn::Banr(rn, rm, rd) => (rd, Outcome::Value(binary(&regs[*rn], &regs[*rm], "&", |a, b| Ok(a & b))?)),
+        Insn::Bani(rn, im, rd) => (rd, Outcome::Value(binary(&regs[*rn], &imm(im), "&", |a, b| Ok(a & b))?)),
+        Insn::Borr(rn, rm, rd) => (rd, Outcome::Value(binary(&regs[*rn], &regs[*rm], "|", |a, b| Ok(a | b))?)),
+        Insn::Bori(rn, im, rd) => (rd, Outcome::Value(binary(&regs[*rn], &imm(im), "|", |a, b| Ok(a | b))?)),
+        Insn::Setr(rn, _, rd) => (rd, Outcome::Value(regs[*rn].clone())),
+        Insn::Seti(im, _, rd) => (rd, Outcome::Value(imm(im))),
+        Insn::Gtir(im, rm, rd) => (rd, compare(&imm(im), &regs[*rm], false, domain)),
+        Insn::Gtri(rn, im, rd) => (rd, compare(&regs[*rn], &imm(im), false, domain)),
+        Insn::Gtrr(rn, rm, rd) => (rd, compare(&regs[*rn], &regs[*rm], false, domain)),
+        Insn::Eqir(im, rm, rd) => (rd, compare(&imm(im), &regs[*rm], true, domain)),
+        Insn::Eqri(rn, im, rd) => (rd, compare(&regs[*rn], &imm(im), true, domain)),
+        Insn::Eqrr(rn, rm, rd) => (rd, compare(&regs[*rn], &regs[*rm], true, domain)),
+        Insn::Divr(rn, rm, rd) => (rd, Outcome::Value(binary(&regs[*rn], &regs[*rm], "/", |a, b| arith.div(a, b))?)),
+        Insn::Divi(rn, im, rd) => (rd, Outcome::Value(binary(&regs[*rn], &imm(im), "/", |a, b| arith.div(a, b))?)),
+        Insn::Modr(rn, rm, rd) => (rd, Outcome::Value(binary(&regs[*rn], &regs[*rm], "%", |a, b| arith.rem(a, b))?)),
+        Insn::Modi(rn, im, rd) => (rd, Outcome::Value(binary(&regs[*rn], &imm(im), "%", |a, b| arith.rem(a, b))?)),
+        Insn::Shlr(rn, rm, rd) => (rd, Outcome::Value(binary(&regs[*rn], &regs[*rm], "<<", |a, b| Ok(arith.shift_left(a, b)))?)),
+        Insn::Shli(rn, im, rd) => (rd, Outcome::Value(binary(&regs[*rn], &imm(im), "<<", |a, b| Ok(arith.shift_left(a, b)))?)),
+        Insn::Shrr(rn, rm, rd) => (rd, Outcome::Value(binary(&regs[*rn], &regs[*rm], ">>", |a, b| Ok(arith.shift_right(a, b)))?)),
+        Insn::Shri(rn, im, rd) => (rd, Outcome::Value(binary(&regs[*rn], &imm(im), ">>", |a, b| Ok(arith.shift_right(a, b)))?)),
+        Insn::Out(rn, _, _) => {
+            let event = format!("pc {}: outputs {}", state.pc, regs[*rn]);
+            state.events.push(event);
+            return Ok(Outcome::Value(Value::Linear(0, 0)));
+        }
+        Insn::In(_, _, rd) => (rd, Outcome::Value(Value::Opaque(format!("input@{}", state.pc)))),
+    };
+    if let Outcome::Value(ref value) = outcome {
+        state.regs[*rd] = value.clone();
+    }
+    Ok(outcome)
+}
+
+fn describe(idiom: &Idiom, regs: &[Value]) -> String {
+    match *idiom {
+        Idiom::DivisorSum { outer, target, sum, .. } => format!(
+            "r{} += sum of the divisors of r{} = {} from r{} = {} up",
+            sum, target, regs[target], outer, regs[outer]
+        ),
+        Idiom::DivideLoop { quot, dividend, divisor, .. } => {
+            format!("r{} = r{} / {} with r{} = {}", quot, dividend, divisor, dividend, regs[dividend])
+        }
+    }
+}
+
+// Where the ip sends the path next, splitting it if that depends on r0
+fn next_pc(state: &State, ip_reg: usize, len: usize) -> Result<Option<usize>, Outcome> {
+    match state.regs[ip_reg] {
+        Value::Linear(0, ip) => Ok(if ip >= -1 && ip + 1 < len as Imm { Some((ip + 1) as usize) } else { None }),
+        Value::Linear(a, b) => {
+            let mut domains = Vec::new();
+            let mut rest = state.domain.clone();
+            for pc in 0..len as Imm {
+                if let Decision::Split(mut split) = decide_zero(a, b + 1 - pc, &rest) {
+                    rest = split.pop().unwrap();
+                    domains.append(&mut split);
+                }
+            }
+            if domains.is_empty() {
+                return Ok(None);
+            }
+            if !rest.is_empty() {
+                domains.push(rest);
+            }
+            Err(Outcome::Split(domains))
+        }
+        Value::Opaque(_) => Err(Outcome::Value(state.regs[ip_reg].clone())),
+    }
+}
+
+// Follows the program from pc 0 with r0 anywhere in lo..=hi and the other registers 0
+pub fn explore(program: &[Insn], ip_reg: usize, arith: Arith, lo: Imm, hi: Imm) -> Vec<Path> {
+    let idioms = optimize(program, ip_reg);
+    let mut regs = vec![Value::Linear(0, 0); NUM_REG];
+    regs[0] = Value::Linear(1, 0);
+    let start = State {
+        domain: Domain { lo, hi, excluded: Vec::new() },
+        regs,
+        pc: 0,
+        executed: 0,
+        visited: vec![false; program.len()],
+        events: Vec::new(),
+        summaries: Vec::new(),
+    };
+    let mut work = vec![start.split(start.domain.clone())];
+    let mut paths = Vec::new();
+
+    while let Some(mut state) = work.pop() {
+        // None when the path was split, the parts are on the work list then
+        let end = loop {
+            let pc = state.pc;
+            if state.visited[pc] {
+                break Some(End::Loop(pc));
+            }
+            let before = state.clone();
+            state.regs[ip_reg] = Value::Linear(0, pc as Imm);
+
+            if let Some(idiom) = &idioms[pc] {
+                let description = describe(idiom, &state.regs);
+                let concrete: Option<Vec<Imm>> = state.regs.iter().map(|r| r.constant()).collect();
+                let mut regs = [0; NUM_REG];
+                let applied = concrete.and_then(|c| {
+                    regs.copy_from_slice(&c);
+                    apply_idiom(idiom, &mut regs, arith)
+                });
+                match applied {
+                    Some((exit, executed)) => {
+                        // The registers as the instruction at the exit sees them
+                        regs[ip_reg] = exit as Imm;
+                        state.events.push(format!("pc {}: {}, giving {:?}", pc, description, regs));
+                        state.summaries.push((pc, before.regs.clone()));
+                        state.regs = regs.iter().map(|r| Value::Linear(0, *r)).collect();
+                        state.regs[ip_reg] = Value::Linear(0, exit as Imm - 1);
+                        state.executed = state.executed.saturating_add(executed);
+                    }
+                    None => {
+                        state.events.push(format!("pc {}: {}", pc, description));
+                        break Some(End::Unknown(pc, "loop with registers depending on r0".to_string()));
+                    }
+                }
+            } else {
+                match execute(&program[pc], &mut state, arith) {
+                    Err(e) => break Some(End::Stopped(pc, e)),
+                    Ok(Outcome::Split(domains)) => {
+                        work.extend(domains.into_iter().rev().map(|d| before.split(d)));
+                        break None;
+                    }
+                    Ok(Outcome::Value(_)) => state.executed += 1,
+                }
+            }
+            state.visited[pc] = true;
+
+            match next_pc(&state, ip_reg, program.len()) {
+                Ok(Some(next)) => state.pc = next,
+                Ok(None) => break Some(End::Halted(state.regs[ip_reg].clone())),
+                Err(Outcome::Split(domains)) => {
+                    work.extend(domains.into_iter().rev().map(|d| before.split(d)));
+                    break None;
+                }
+                Err(Outcome::Value(ip)) => break Some(End::Unknown(pc, format!("jumps to {} + 1", ip))),
+            }
+        };
+        if let Some(end) = end {
+            paths.push(Path {
+                domain: state.domain,
+                regs: state.regs,
+                executed: state.executed,
+                events: state.events,
+                summaries: state.summaries,
+                end,
+            });
+        }
+    }
+    paths.sort_by_key(|p| p.domain.lo);
+    paths
+}
@@ -0,0 +1,311 @@
+// Execution traces of the elfcode VM.
+//
+// A trace is the list of register states the program went through, state 0 being the registers
+// before the first instruction. Most states are stored as the difference to the state before it,
+// and every `interval` states there is a full snapshot, so any state can be rebuilt by decoding
+// from the snapshot before it. The file ends in an index of where the snapshots are, so a trace
+// is read by seeking to a snapshot rather than by loading it whole.
+//
+// A run recorded with the idioms optimized away records one state per step of the VM, and a
+// replaced loop is one step however many instructions it stands for. State n of such a trace is
+// not the state after n executed instructions, only a trace of a plain run has one state per
+// instruction.
+//
+// File layout, all numbers as LEB128 varints unless noted:
+//   "ELFTRACE", ip register, snapshot interval, program text length, program text one insn per line
+//   then one record per state:
+//     snapshot: 0xff, then every register zigzag encoded
+//     delta:    bitmask of changed registers, then for each the zigzag encoded change.
+//               The ip register is compared to the previous value plus one, so plain
+//               fall-through costs nothing.
+//   then the index, as little endian u64s: the file offset of every snapshot, the number of
+//   states, the number of snapshots, and "ELFINDEX"
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use super::{Imm, Insn, Reg, NUM_REG};
+
+const MAGIC: &[u8] = b"ELFTRACE";
+const INDEX_MAGIC: &[u8] = b"ELFINDEX";
+const SNAPSHOT: u8 = 0xff;
+
+type Regs = [Imm; NUM_REG];
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u128) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0u8];
+    match input.read_exact(&mut byte) {
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(invalid("trace ends in the middle of a record".to_string())),
+        result => result.map(|_| byte[0]),
+    }
+}
+
+fn read_varint<R: Read>(input: &mut R) -> io::Result<u128> {
+    let mut v = 0u128;
+    let mut shift = 0;
+    loop {
+        let byte = read_byte(input)?;
+        if shift >= 128 {
+            return Err(invalid("varint is too long".to_string()));
+        }
+        v |= ((byte & 0x7f) as u128) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(v);
+        }
+        shift += 7;
+    }
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn zigzag(v: Imm) -> u128 {
+    ((v << 1) ^ (v >> 127)) as u128
+}
+
+fn unzigzag(v: u128) -> Imm {
+    ((v >> 1) as Imm) ^ -((v & 1) as Imm)
+}
+
+fn expected(prev: &Regs, ip_reg: Reg, r: usize) -> Imm {
+    if r == ip_reg { prev[r].wrapping_add(1) } else { prev[r] }
+}
+
+pub struct TraceWriter {
+    out: BufWriter<File>,
+    buf: Vec<u8>,
+    ip_reg: Reg,
+    interval: u64,
+    max_states: u64,
+    states: u64,
+    prev: Regs,
+    // Bytes written so far, and where each snapshot starts
+    offset: u64,
+    snapshots: Vec<u64>,
+}
+
+impl TraceWriter {
+    // Starts a trace with the registers before the first instruction. Recording stops after max_states states.
+    pub fn create(path: &str, program: &[Insn], ip_reg: Reg, interval: u64, max_states: u64, regs: &Regs) -> io::Result<TraceWriter> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let program_text: String = program.iter().map(|insn| format!("{}\n", insn)).collect();
+        let mut header = MAGIC.to_vec();
+        write_varint(&mut header, ip_reg as u128);
+        write_varint(&mut header, interval as u128);
+        write_varint(&mut header, program_text.len() as u128);
+        header.extend_from_slice(program_text.as_bytes());
+        out.write_all(&header)?;
+        let mut writer = TraceWriter {
+            out, buf: Vec::new(), ip_reg, interval, max_states, states: 0, prev: *regs, offset: header.len() as u64, snapshots: Vec::new(),
+        };
+        writer.record(regs)?;
+        Ok(writer)
+    }
+
+    // Adds the next state, returns false when the trace is full
+    pub fn record(&mut self, regs: &Regs) -> io::Result<bool> {
+        self.buf.clear();
+        if self.states.is_multiple_of(self.interval) {
+            self.snapshots.push(self.offset);
+            self.buf.push(SNAPSHOT);
+            for r in regs.iter() {
+                write_varint(&mut self.buf, zigzag(*r));
+            }
+        } else {
+            let mut mask = 0u8;
+            let mut changes = Vec::new();
+            for (r, reg) in regs.iter().enumerate() {
+                let diff = reg.wrapping_sub(expected(&self.prev, self.ip_reg, r));
+                if diff != 0 {
+                    mask |= 1 << r;
+                    changes.push(diff);
+                }
+            }
+            self.buf.push(mask);
+            for diff in changes {
+                write_varint(&mut self.buf, zigzag(diff));
+            }
+        }
+        self.out.write_all(&self.buf)?;
+        self.offset += self.buf.len() as u64;
+        self.prev = *regs;
+        self.states += 1;
+        Ok(self.states < self.max_states)
+    }
+
+    // Writes the snapshot index, a trace without it can't be opened
+    pub fn finish(mut self) -> io::Result<u64> {
+        for offset in &self.snapshots {
+            self.out.write_all(&offset.to_le_bytes())?;
+        }
+        self.out.write_all(&self.states.to_le_bytes())?;
+        self.out.write_all(&(self.snapshots.len() as u64).to_le_bytes())?;
+        self.out.write_all(INDEX_MAGIC)?;
+        self.out.flush()?;
+        Ok(self.states)
+    }
+}
+
+pub struct Trace {
+    pub ip_reg: Reg,
+    pub program: Vec<String>,
+    path: String,
+    interval: u64,
+    // File offset of every snapshot record, and of the end of the records
+    snapshots: Vec<u64>,
+    end: u64,
+    states: u64,
+}
+
+impl Trace {
+    // Reads the header and the snapshot index, the states stay in the file until asked for
+    pub fn open(path: &str) -> io::Result<Trace> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut input = BufReader::new(&mut file);
+        let mut magic = [0u8; 8];
+        if input.read_exact(&mut magic).is_err() || magic != MAGIC {
+            return Err(invalid(format!("{} is not a trace", path)));
+        }
+        let ip_reg = read_varint(&mut input)?;
+        if ip_reg >= NUM_REG as u128 {
+            return Err(invalid(format!("{} has ip register {}, there are only {}", path, ip_reg, NUM_REG)));
+        }
+        let interval = read_varint(&mut input)?;
+        if interval == 0 || interval > u64::MAX as u128 {
+            return Err(invalid(format!("{} has a snapshot interval of {}", path, interval)));
+        }
+        let text_len = read_varint(&mut input)?;
+        let start = input.stream_position()?;
+        if text_len > (len - start) as u128 {
+            return Err(invalid(format!("{} has a program of {} bytes but only {} bytes after the header", path, text_len, len - start)));
+        }
+        let mut text = vec![0u8; text_len as usize];
+        input.read_exact(&mut text)?;
+        let program = String::from_utf8_lossy(&text).lines().map(|l| l.to_string()).collect();
+        let start = start + text_len as u64;
+
+        // The index is at the end, its size comes from the snapshot count just before the magic
+        let no_index = || invalid(format!("{} has no snapshot index, the recording didn't finish", path));
+        if len < start + 24 {
+            return Err(no_index());
+        }
+        input.seek(SeekFrom::Start(len - 24))?;
+        let (states, count) = (read_u64(&mut input)?, read_u64(&mut input)?);
+        input.read_exact(&mut magic)?;
+        if magic != INDEX_MAGIC {
+            return Err(no_index());
+        }
+        let end = count.checked_mul(8).and_then(|size| (len - 24).checked_sub(size)).filter(|end| *end > start)
+            .ok_or_else(|| invalid(format!("{} has an index of {} snapshots that doesn't fit in the file", path, count)))?;
+        if count != states.div_ceil(interval as u64) {
+            return Err(invalid(format!("{} has {} snapshots for {} states every {}", path, count, states, interval)));
+        }
+        input.seek(SeekFrom::Start(end))?;
+        let snapshots = (0..count).map(|_| read_u64(&mut input)).collect::<io::Result<Vec<u64>>>()?;
+        if snapshots.first() != Some(&start) || snapshots.windows(2).any(|w| w[0] >= w[1]) || snapshots.last().is_some_and(|last| *last >= end) {
+            return Err(invalid(format!("{} has snapshot offsets out of order or outside the records", path)));
+        }
+        Ok(Trace { ip_reg: ip_reg as Reg, program, path: path.to_string(), interval: interval as u64, snapshots, end, states })
+    }
+
+    // A reader over the records from the given snapshot on, which has to start with a snapshot record
+    fn seek(&self, snapshot: usize) -> io::Result<io::Take<BufReader<File>>> {
+        let mut file = File::open(&self.path)?;
+        let offset = self.snapshots[snapshot];
+        file.seek(SeekFrom::Start(offset))?;
+        let mut input = BufReader::new(file).take(self.end - offset);
+        let mut marker = [0u8];
+        input.read_exact(&mut marker)?;
+        if marker[0] != SNAPSHOT {
+            return Err(invalid(format!("{}: snapshot {} at byte {} is not a snapshot record", self.path, snapshot, offset)));
+        }
+        Ok(input)
+    }
+
+    // Decodes the next record. The first one after seek has its snapshot marker read already.
+    fn decode<R: Read>(&self, input: &mut R, regs: &mut Regs, snapshot: bool) -> io::Result<()> {
+        let mask = if snapshot { SNAPSHOT } else { read_byte(input)? };
+        if mask == SNAPSHOT {
+            for r in regs.iter_mut() {
+                *r = unzigzag(read_varint(input)?);
+            }
+        } else if mask >> NUM_REG != 0 {
+            return Err(invalid(format!("{}: a record changes registers past r{}", self.path, NUM_REG - 1)));
+        } else {
+            let prev = *regs;
+            for (r, reg) in regs.iter_mut().enumerate() {
+                *reg = expected(&prev, self.ip_reg, r);
+                if mask & (1 << r) != 0 {
+                    *reg = reg.wrapping_add(unzigzag(read_varint(input)?));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Number of states, the last one is the state the program stopped in
+    pub fn len(&self) -> u64 {
+        self.states
+    }
+
+    pub fn state(&self, n: u64) -> io::Result<Option<Regs>> {
+        if n >= self.states {
+            return Ok(None);
+        }
+        let snapshot = n / self.interval;
+        let mut input = self.seek(snapshot as usize)?;
+        let mut regs = [0; NUM_REG];
+        for i in snapshot * self.interval..=n {
+            self.decode(&mut input, &mut regs, i == snapshot * self.interval)?;
+        }
+        Ok(Some(regs))
+    }
+
+    // Every state in order, read from the file as they are needed
+    pub fn states(&self) -> impl Iterator<Item = io::Result<Regs>> + '_ {
+        let mut input = if self.states > 0 { Some(self.seek(0)) } else { None };
+        let mut regs = [0; NUM_REG];
+        (0..self.states).map(move |n| {
+            let input = match input.as_mut().unwrap() {
+                Ok(input) => input,
+                Err(e) => return Err(io::Error::new(e.kind(), e.to_string())),
+            };
+            if n > 0 && n.is_multiple_of(self.interval) && read_byte(input)? != SNAPSHOT {
+                return Err(invalid(format!("{}: state {} should be a snapshot record", self.path, n)));
+            }
+            self.decode(input, &mut regs, n.is_multiple_of(self.interval))?;
+            Ok(regs)
+        })
+    }
+}
+
+// A state number with the registers of both traces there, None past the end of a trace
+pub type Difference = (u64, Option<Regs>, Option<Regs>);
+
+// The first state where the traces differ. A trace ending early differs from the other at its length.
+pub fn diff(a: &Trace, b: &Trace) -> io::Result<Option<Difference>> {
+    let mut a_states = a.states();
+    let mut b_states = b.states();
+    for n in 0.. {
+        match (a_states.next().transpose()?, b_states.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (ra, rb) if ra != rb => return Ok(Some((n, ra, rb))),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
@@ -1,8 +1,11 @@
 extern crate regex;
 use regex::Regex;
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
 
-type Imm = i64;
+// Wide enough for every register width, results are fitted to the width by Arith
+type Imm = i128;
 type Reg = usize;
 const NUM_REG: usize = 6;
 
@@ -26,29 +29,151 @@ enum Insn {
     Eqrr(Reg, Reg, Reg),
 }
 
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Insn::Addr(a, b, c) => write!(f, "addr {} {} {}", a, b, c),
+            Insn::Addi(a, b, c) => write!(f, "addi {} {} {}", a, b, c),
+            Insn::Mulr(a, b, c) => write!(f, "mulr {} {} {}", a, b, c),
+            Insn::Muli(a, b, c) => write!(f, "muli {} {} {}", a, b, c),
+            Insn::Banr(a, b, c) => write!(f, "banr {} {} {}", a, b, c),
+            Insn::Bani(a, b, c) => write!(f, "bani {} {} {}", a, b, c),
+            Insn::Borr(a, b, c) => write!(f, "borr {} {} {}", a, b, c),
+            Insn::Bori(a, b, c) => write!(f, "bori {} {} {}", a, b, c),
+            Insn::Setr(a, b, c) => write!(f, "setr {} {} {}", a, b, c),
+            Insn::Seti(a, b, c) => write!(f, "seti {} {} {}", a, b, c),
+            Insn::Gtir(a, b, c) => write!(f, "gtir {} {} {}", a, b, c),
+            Insn::Gtri(a, b, c) => write!(f, "gtri {} {} {}", a, b, c),
+            Insn::Gtrr(a, b, c) => write!(f, "gtrr {} {} {}", a, b, c),
+            Insn::Eqir(a, b, c) => write!(f, "eqir {} {} {}", a, b, c),
+            Insn::Eqri(a, b, c) => write!(f, "eqri {} {} {}", a, b, c),
+            Insn::Eqrr(a, b, c) => write!(f, "eqrr {} {} {}", a, b, c),
+        }
+    }
+}
+
+// Register width. The hash in the input masks every value to 24 bits, so the width only matters
+// for other programs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Width {
+    I64,
+    U64,
+    I128,
+}
+
+// What addr, addi, mulr and muli do when the result doesn't fit, the idioms overflow the same way
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Overflow {
+    // Stops the run with an error instead of reporting halt values
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Arith {
+    width: Width,
+    overflow: Overflow,
+}
+
+impl Default for Arith {
+    // i64 registers that wrap, what the halt values were searched with before there was a choice
+    fn default() -> Arith {
+        Arith { width: Width::I64, overflow: Overflow::Wrapping }
+    }
+}
+
+impl Arith {
+    fn in_range(&self, v: Imm) -> bool {
+        match self.width {
+            Width::I64 => v >= i64::MIN as Imm && v <= i64::MAX as Imm,
+            Width::U64 => v >= 0 && v <= u64::MAX as Imm,
+            Width::I128 => true,
+        }
+    }
+
+    // Truncates to the width like a two's complement register would, the constants of the hash go
+    // through this too
+    fn wrap(&self, v: Imm) -> Imm {
+        match self.width {
+            Width::I64 => v as i64 as Imm,
+            Width::U64 => v as u64 as Imm,
+            Width::I128 => v,
+        }
+    }
+
+    // exact is None when the result doesn't even fit in i128, positive tells which way to saturate
+    fn fit(&self, exact: Option<Imm>, wrapped: Imm, positive: bool) -> Result<Imm, &'static str> {
+        match exact {
+            Some(v) if self.in_range(v) => Ok(v),
+            _ => match self.overflow {
+                Overflow::Checked => Err("overflow"),
+                Overflow::Wrapping => Ok(self.wrap(wrapped)),
+                Overflow::Saturating => Ok(match (self.width, positive) {
+                    (Width::I64, true) => i64::MAX as Imm,
+                    (Width::I64, false) => i64::MIN as Imm,
+                    (Width::U64, true) => u64::MAX as Imm,
+                    (Width::U64, false) => 0,
+                    (Width::I128, true) => Imm::MAX,
+                    (Width::I128, false) => Imm::MIN,
+                }),
+            },
+        }
+    }
+
+    fn add(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        let exact = a.checked_add(b);
+        self.fit(exact, a.wrapping_add(b), exact.map_or(a > 0, |v| v > 0))
+    }
+
+    fn mul(&self, a: Imm, b: Imm) -> Result<Imm, &'static str> {
+        let exact = a.checked_mul(b);
+        self.fit(exact, a.wrapping_mul(b), exact.map_or((a > 0) == (b > 0), |v| v > 0))
+    }
+}
 
-fn execute(insn: &Insn, regs: &mut [Imm; NUM_REG]) {
+// `--i64/--u64/--i128` picks the register width and `--checked/--wrapping/--saturating` what overflow does
+fn parse_arith(args: &[String]) -> Arith {
+    let mut arith = Arith::default();
+    for arg in args {
+        match arg.as_str() {
+            "--i64" => arith.width = Width::I64,
+            "--u64" => arith.width = Width::U64,
+            "--i128" => arith.width = Width::I128,
+            "--checked" => arith.overflow = Overflow::Checked,
+            "--wrapping" => arith.overflow = Overflow::Wrapping,
+            "--saturating" => arith.overflow = Overflow::Saturating,
+            _ => {}
+        }
+    }
+    arith
+}
+
+fn execute(insn: &Insn, regs: &mut [Imm; NUM_REG], arith: Arith) -> Result<(), &'static str> {
+    let imm = |im: &Imm| arith.wrap(*im);
     match insn {
-        Insn::Addr(rn, rm, rd) => regs[*rd] = regs[*rn] + regs[*rm],
-        Insn::Addi(rn, im, rd) => regs[*rd] = regs[*rn] + im,
-        Insn::Mulr(rn, rm, rd) => regs[*rd] = regs[*rn] * regs[*rm],
-        Insn::Muli(rn, im, rd) => regs[*rd] = regs[*rn] * im,
+        Insn::Addr(rn, rm, rd) => regs[*rd] = arith.add(regs[*rn], regs[*rm])?,
+        Insn::Addi(rn, im, rd) => regs[*rd] = arith.add(regs[*rn], imm(im))?,
+        Insn::Mulr(rn, rm, rd) => regs[*rd] = arith.mul(regs[*rn], regs[*rm])?,
+        Insn::Muli(rn, im, rd) => regs[*rd] = arith.mul(regs[*rn], imm(im))?,
         Insn::Banr(rn, rm, rd) => regs[*rd] = regs[*rn] & regs[*rm],
-        Insn::Bani(rn, im, rd) => regs[*rd] = regs[*rn] & im,
+        Insn::Bani(rn, im, rd) => regs[*rd] = regs[*rn] & imm(im),
         Insn::Borr(rn, rm, rd) => regs[*rd] = regs[*rn] | regs[*rm],
-        Insn::Bori(rn, im, rd) => regs[*rd] = regs[*rn] | im,
+        Insn::Bori(rn, im, rd) => regs[*rd] = regs[*rn] | imm(im),
         Insn::Setr(rn, _, rd) => regs[*rd] = regs[*rn],
-        Insn::Seti(im, _, rd) => regs[*rd] = *im,
-        Insn::Gtir(im, rm, rd) => regs[*rd] = if *im > regs[*rm] { 1 } else { 0 },
-        Insn::Gtri(rn, im, rd) => regs[*rd] = if regs[*rn] > *im { 1 } else { 0 },
+        Insn::Seti(im, _, rd) => regs[*rd] = imm(im),
+        Insn::Gtir(im, rm, rd) => regs[*rd] = if imm(im) > regs[*rm] { 1 } else { 0 },
+        Insn::Gtri(rn, im, rd) => regs[*rd] = if regs[*rn] > imm(im) { 1 } else { 0 },
         Insn::Gtrr(rn, rm, rd) => regs[*rd] = if regs[*rn] > regs[*rm] { 1 } else { 0 },
-        Insn::Eqir(im, rm, rd) => regs[*rd] = if *im == regs[*rm] { 1 } else { 0 },
-        Insn::Eqri(rn, im, rd) => regs[*rd] = if regs[*rn] == *im { 1 } else { 0 },
+        Insn::Eqir(im, rm, rd) => regs[*rd] = if imm(im) == regs[*rm] { 1 } else { 0 },
+        Insn::Eqri(rn, im, rd) => regs[*rd] = if regs[*rn] == imm(im) { 1 } else { 0 },
         Insn::Eqrr(rn, rm, rd) => regs[*rd] = if regs[*rn] == regs[*rm] { 1 } else { 0 },
-        _ => panic!("Unknown instruction")
     };
+    Ok(())
 }
 
+// The sixteen opcodes of the puzzle. The extended div, mod, shl, shr, in and out of dec19 aren't
+// here, a program using them stops at the panic.
 fn parse_insn(insn_order: &str, insn: &[Imm; 3]) -> Insn {
     match insn_order {
         "addr" => Insn::Addr(insn[0] as Reg, insn[1] as Reg, insn[2] as Reg),
@@ -71,28 +196,142 @@ fn parse_insn(insn_order: &str, insn: &[Imm; 3]) -> Insn {
     }
 }
 
-fn run_program(program: &Vec<Insn>, ip_reg: usize, regs: &mut [Imm; NUM_REG], analyze: bool) -> (u64, bool, HashMap<Imm, Imm>) {
+// Loop shapes that can be replaced by a native computation, found by optimize(). This is the
+// optimizer of dec19 without its divisor sum, which only the day 19 program has; a fix to the
+// divide loop belongs in both.
+#[derive(Debug)]
+enum Idiom {
+    // quot = 0; while (quot + 1) * divisor <= dividend { quot += 1 }
+    DivideLoop { quot: Reg, tmp: Reg, dividend: Reg, divisor: Imm, exit: usize },
+}
+
+// Returns the register that isn't `known` if the pair contains it, for commutative operands
+fn other_operand(a: Reg, b: Reg, known: Reg) -> Option<Reg> {
+    if a == known {
+        Some(b)
+    } else if b == known {
+        Some(a)
+    } else {
+        None
+    }
+}
+
+fn all_distinct(regs: &[Reg]) -> bool {
+    regs.iter().enumerate().all(|(i, r)| !regs[i + 1..].contains(r))
+}
+
+fn match_divide_loop(program: &[Insn], ip: Reg, start: usize) -> Option<Idiom> {
+    let code = program.get(start..start + 9)?;
+    let quot = match code[0] { Insn::Seti(0, _, c) => c, _ => return None };
+    let tmp = match code[1] { Insn::Addi(x, 1, d) if x == quot => d, _ => return None };
+    let divisor = match code[2] { Insn::Muli(x, k, d) if x == tmp && d == tmp && k > 0 => k, _ => return None };
+    let dividend = match code[3] { Insn::Gtrr(x, y, d) if x == tmp && d == tmp => y, _ => return None };
+    match code[4] { Insn::Addr(x, y, d) if d == ip && other_operand(x, y, ip)? == tmp => {}, _ => return None };
+    match code[5] { Insn::Addi(x, 1, d) if x == ip && d == ip => {}, _ => return None };
+    match code[6] { Insn::Seti(v, _, d) if d == ip && v == (start + 8) as Imm => {}, _ => return None };
+    match code[7] { Insn::Addi(x, 1, d) if x == quot && d == quot => {}, _ => return None };
+    match code[8] { Insn::Seti(v, _, d) if d == ip && v == start as Imm => {}, _ => return None };
+
+    if !all_distinct(&[ip, quot, tmp, dividend]) {
+        return None;
+    }
+    Some(Idiom::DivideLoop { quot, tmp, dividend, divisor, exit: start + 9 })
+}
+
+// Finds the known idioms in the program, indexed by the pc they start at
+fn optimize(program: &[Insn], ip_reg: Reg) -> Vec<Option<Idiom>> {
+    (0..program.len())
+        .map(|pc| match_divide_loop(program, ip_reg, pc))
+        .collect()
+}
+
+// Performs the idiom on the registers and returns the pc to continue at, and how many instructions
+// the interpreter would have executed to get there. None if the registers are outside what the native version handles, then the loop is interpreted instead.
+fn apply_idiom(idiom: &Idiom, regs: &mut [Imm; NUM_REG], arith: Arith) -> Option<(usize, u64)> {
+    match *idiom {
+        Idiom::DivideLoop { quot, tmp, dividend, divisor, exit } => {
+            // The loop multiplies up to dividend + divisor
+            if arith.wrap(divisor) != divisor || !arith.in_range(regs[dividend] + divisor) {
+                return None;
+            }
+            regs[quot] = if regs[dividend] < 0 { 0 } else { regs[dividend] / divisor };
+            regs[tmp] = 1;
+            // 7 instructions per increment of quot, plus the setup and the exit
+            Some((exit, (regs[quot] as u64).saturating_mul(7).saturating_add(6)))
+        }
+    }
+}
+
+fn dest_reg(insn: &Insn) -> Reg {
+    match insn {
+        Insn::Addr(_, _, rd) | Insn::Addi(_, _, rd) | Insn::Mulr(_, _, rd) | Insn::Muli(_, _, rd) |
+        Insn::Banr(_, _, rd) | Insn::Bani(_, _, rd) | Insn::Borr(_, _, rd) | Insn::Bori(_, _, rd) |
+        Insn::Setr(_, _, rd) | Insn::Seti(_, _, rd) | Insn::Gtir(_, _, rd) | Insn::Gtri(_, _, rd) |
+        Insn::Gtrr(_, _, rd) | Insn::Eqir(_, _, rd) | Insn::Eqri(_, _, rd) | Insn::Eqrr(_, _, rd) => *rd,
+    }
+}
+
+// Finds the check deciding if the program halts: an eqrr comparing a register to r0, followed by
+// a jump over the end of the program when they are equal. r0 must only be read, so it works as the input.
+// Returns the pc of the eqrr and the register that r0 is compared to.
+fn find_halt_check(program: &[Insn], ip_reg: Reg) -> Option<(usize, Reg)> {
+    if ip_reg == 0 || program.iter().any(|insn| dest_reg(insn) == 0) {
+        return None;
+    }
+    (0..program.len()).find_map(|pc| {
+        let (reg, tmp) = match program[pc] {
+            Insn::Eqrr(0, rm, rd) | Insn::Eqrr(rm, 0, rd) if rm != 0 && rm != ip_reg => (rm, rd),
+            _ => return None,
+        };
+        // Equal skips the instruction after the jump, which must leave the program
+        match program.get(pc + 1) {
+            Some(Insn::Addr(rn, rm, rd)) if *rd == ip_reg && ((*rn == tmp && *rm == ip_reg) || (*rn == ip_reg && *rm == tmp)) => {}
+            _ => return None,
+        }
+        if pc + 3 < program.len() {
+            return None;
+        }
+        Some((pc, reg))
+    })
+}
+
+// With a halt check given, records the first instruction count at which each value is compared to r0,
+// until a value repeats. With checked arithmetic an overflow stops the program with an error.
+fn run_program(program: &[Insn], ip_reg: usize, regs: &mut [Imm; NUM_REG], halt_check: Option<(usize, Reg)>, arith: Arith, optimized: bool) -> Result<(u64, bool, HashMap<Imm, Imm>), String> {
+    let idioms = if optimized { optimize(program, ip_reg) } else { Vec::new() };
+    for (pc, idiom) in idioms.iter().enumerate() {
+        if let Some(idiom) = idiom {
+            println!("Replacing loop at pc {} with {:?}", pc, idiom);
+        }
+    }
+
     let mut count = 0u64;
     let mut halted = false;
-    let mut reg3s: HashMap<Imm, Imm> = HashMap::new();
+    let mut compared: HashMap<Imm, Imm> = HashMap::new();
     loop {
         let pc = regs[ip_reg] as usize;
-        // if count % 100_000_000 == 0 {
-            if pc == 28 && analyze {
-                let size = reg3s.len();
-                if !reg3s.contains_key(&regs[3]) {
-                    reg3s.insert(regs[3], count as Imm);
-                }
-                if reg3s.len() == size {
+        if let Some((check_pc, check_reg)) = halt_check {
+            if pc == check_pc {
+                let size = compared.len();
+                compared.entry(regs[check_reg]).or_insert(count as Imm);
+                if compared.len() == size {
                     println!("No new value detected after {}", count);
                     break;
                 }
             }
-        // }
-        execute(&program[pc], regs);
-        count += 1;
+        }
+        if let Some((exit, executed)) = idioms.get(pc).and_then(|i| i.as_ref()).and_then(|i| apply_idiom(i, regs, arith)) {
+            regs[ip_reg] = exit as Imm - 1;
+            count += executed;
+        } else {
+            let before = *regs;
+            if let Err(e) = execute(&program[pc], regs, arith) {
+                return Err(format!("pc {}: {} stopped with {} after {} instructions, registers {:?}", pc, program[pc], e, count, before));
+            }
+            count += 1;
+        }
 
-        if regs[ip_reg] >= 0 && ((regs[ip_reg] + 1i64) as usize) < program.len() {
+        if regs[ip_reg] >= -1 && ((regs[ip_reg] + 1) as usize) < program.len() {
             regs[ip_reg] += 1;
         } else {
             halted = true;
@@ -101,10 +340,13 @@ fn run_program(program: &Vec<Insn>, ip_reg: usize, regs: &mut [Imm; NUM_REG], an
     }
     println!("Executed {}", count);
 
-    return (count, halted, reg3s);
+    Ok((count, halted, compared))
 }
 
 fn main() {
+    // `cargo run -- --checked` and the other flags of parse_arith pick the arithmetic
+    let args: Vec<String> = env::args().collect();
+    let arith = parse_arith(&args);
     let input = include_str!("input");
 
     let ip_r = Regex::new(r"#ip (\d+)").unwrap(); 
@@ -128,13 +370,36 @@ fn main() {
         }
     }
 
-    // Looking at the input, the eqrr is seen comparing reg3 to reg0, and if they are equal the program will halt
-    // Run and collect all the values we see for reg3 at this position, also store the execution count, then find min max
+    // The program halts when r0 equals the register it is compared to in the halt check.
+    // Run and collect all the values we see for that register at the check, also store the execution count, then find min max
     println!("Analyzing instructions...");
+    let ip_reg = ip_reg_id.expect("should have a ip reg");
+    let halt_check = find_halt_check(&program, ip_reg).expect("no eqrr against r0 that halts the program");
+    println!("Halt check at pc {} compares r0 to r{}", halt_check.0, halt_check.1);
     let mut regs: [Imm; NUM_REG] = [0, 0, 0, 0, 0, 0];
-    let (_, _, reg3s) = run_program(&program, ip_reg_id.expect("should have a ip reg"), &mut regs, true);
-    let lowest = reg3s.iter().min_by_key(|(zeroval, &count)| count).unwrap();
-    let highest = reg3s.iter().max_by_key(|(zeroval, &count)| count).unwrap();
+    let (_, _, compared) = match run_program(&program, ip_reg, &mut regs, Some(halt_check), arith, true) {
+        Ok(result) => result,
+        Err(e) => { println!("{}", e); return; }
+    };
+    let lowest = compared.iter().min_by_key(|(_, &count)| count).unwrap();
+    let highest = compared.iter().max_by_key(|(_, &count)| count).unwrap();
     println!("Part1: reg-0-value {} only executes {} instructions", lowest.0, lowest.1);
     println!("Part2: reg-0-value {} executes the most {} instructions", highest.0, highest.1);
+    // The values stay well inside 24 bits, so every width and overflow mode gives the same answers
+    assert!((*lowest.0, *lowest.1) == (3909249, 1846) && (*highest.0, *highest.1) == (12333799, 2600949687));
+
+    // With r0 set to the part 1 value the input halts after the same instructions in every mode
+    let modes = [Width::I64, Width::U64, Width::I128].iter().flat_map(|&width| {
+        [Overflow::Checked, Overflow::Wrapping, Overflow::Saturating].iter().map(move |&overflow| Arith { width, overflow })
+    });
+    let halts: Vec<(u64, bool)> = modes.map(|arith| {
+        let mut regs: [Imm; NUM_REG] = [*lowest.0, 0, 0, 0, 0, 0];
+        run_program(&program, ip_reg, &mut regs, None, arith, false).map(|(count, halted, _)| (count, halted)).unwrap()
+    }).collect();
+    assert!(halts.len() == 9 && halts[0].1 && halts.iter().all(|halt| *halt == halts[0]));
+    // Checked overflow stops the run at the instruction, 2^62 * 4 doesn't fit in i64
+    let big = vec![Insn::Seti(1 << 62, 0, 0), Insn::Muli(0, 4, 0)];
+    let mut regs: [Imm; NUM_REG] = [0; NUM_REG];
+    let stopped = run_program(&big, 5, &mut regs, None, Arith { width: Width::I64, overflow: Overflow::Checked }, false).err();
+    assert!(stopped == Some("pc 1: muli 0 4 0 stopped with overflow after 1 instructions, registers [4611686018427387904, 0, 0, 0, 0, 1]".to_string()));
 }